@@ -0,0 +1,87 @@
+//! Compares the rope backing `File` with the `Vec<UnicodeString>` lines it replaced, on multi-megabyte text.
+//! Run with `cargo bench`.
+
+#![allow(clippy::needless_return)]
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/app/unicode.rs"]
+mod unicode;
+
+#[allow(dead_code)]
+#[path = "../src/app/file/mapped.rs"]
+mod mapped;
+
+#[allow(dead_code)]
+#[path = "../src/app/file/text.rs"]
+mod text;
+
+use text::Text;
+use unicode::UnicodeString;
+
+/// About 8 MB of short lines followed by a single 2 MB line, with some multibyte chars.
+fn sample() -> String {
+    let mut content = String::new();
+    for line_idx in 0..100_000 {
+        content.push_str(&format!("{:>6}: the quick brown fox jumps över the lazy dog — {}\n", line_idx, "x".repeat(line_idx % 20)));
+    }
+    content.push_str(&"lorem ipsum dölor sit amet ".repeat(80_000));
+    return content;
+}
+
+fn load(c: &mut Criterion) {
+    let content = sample();
+    let mut group = c.benchmark_group("load");
+    group.sample_size(10);
+    group.bench_function("lines", |b| b.iter(|| content.split('\n').map(UnicodeString::from).collect::<Vec<_>>()));
+    group.bench_function("rope", |b| b.iter(|| Text::from(black_box(&content))));
+    group.finish();
+}
+
+fn type_in_long_line(c: &mut Criterion) {
+    let content = sample();
+    let lines: Vec<UnicodeString> = content.split('\n').map(UnicodeString::from).collect();
+    let line_idx = lines.len() - 1;
+    let char_idx = lines[line_idx].length() / 2;
+
+    let mut group = c.benchmark_group("type_in_long_line");
+    group.bench_function("lines", |b| b.iter_batched_ref(
+        || lines[line_idx].clone(),
+        |line| for ch in "hello".chars() { line.insert(char_idx, ch) },
+        BatchSize::LargeInput,
+    ));
+    group.bench_function("rope", |b| b.iter_batched_ref(
+        || Text::from(&content),
+        |text| for ch in "hello".chars() { text.insert((char_idx, line_idx), ch.encode_utf8(&mut [0; 4])); },
+        BatchSize::LargeInput,
+    ));
+    group.finish();
+}
+
+fn break_line_in_middle(c: &mut Criterion) {
+    let content = sample();
+    let lines: Vec<UnicodeString> = content.split('\n').map(UnicodeString::from).collect();
+    let line_idx = lines.len() / 2;
+
+    let mut group = c.benchmark_group("break_line_in_middle");
+    group.bench_function("lines", |b| b.iter_batched_ref(
+        || lines.clone(),
+        |lines| lines.insert(line_idx, UnicodeString::new()),
+        BatchSize::LargeInput,
+    ));
+    group.bench_function("rope", |b| b.iter_batched_ref(
+        || Text::from(&content),
+        |text| { text.insert((0, line_idx), "\n"); },
+        BatchSize::LargeInput,
+    ));
+    group.finish();
+}
+
+fn index_line(c: &mut Criterion) {
+    let text = Text::from(&sample());
+    c.bench_function("index_line/rope", |b| b.iter(|| text.line_length(black_box(54_321))));
+}
+
+criterion_group!(benches, load, type_in_long_line, break_line_in_middle, index_line);
+criterion_main!(benches);
@@ -1,40 +1,110 @@
 use std::{io, time::Duration};
 
-use crossterm::event;
+use crossterm::{event, execute};
 use tui::{Terminal, backend::Backend};
 
-use self::{rendering::render, functionality::handle_event, file::File, prompt::Prompt};
+use self::{rendering::render, functionality::{handle_event, update}, buffers::Buffers, config::Config, file::{Encoding, File, View, ReplaceSession, SearchOptions}, grep::Grep, project_replace::ProjectReplace, menu::Menu, prompt::Prompt, registers::{Registers, SetClipboard}, windows::Windows};
 
-mod file;
+mod buffers;
+mod config;
+pub mod file;
+mod grep;
+mod menu;
+mod project_replace;
 mod prompt;
+mod registers;
 mod rendering;
 mod functionality;
 mod unicode;
+mod windows;
 
 #[derive(PartialEq)]
-pub enum State { Editing, Saving, Overwriting, Quitting }
+pub enum State { Editing, SelectingRegister, Searching, Replacing, ReplacingWith, ConfirmingReplace, Grepping, BrowsingGrep, ProjectReplacing, ProjectReplacingWith, PreviewingReplace, ReadingReport, Opening, Saving, Overwriting, ListingBuffers, TimeTraveling, BrowsingHistory, SettingIndentation, SettingLineEnding, ReopeningWithEncoding, SavingWithEncoding, Quitting }
 
 pub struct Data {
     state: State,
-    file: File,
+    buffers: Buffers,
+    windows: Windows,
+    message: Option<String>,
+    registers: Registers,
+    search_prompt: Prompt,
+    search_origin: (View, (usize, usize)),
+    search_options: SearchOptions,
+    replace_prompt: Prompt,
+    replace_with_prompt: Prompt,
+    replace_session: Option<ReplaceSession>,
+    grep_prompt: Prompt,
+    grep: Option<Grep>,
+    grep_menu: Menu,
+    project_replace_prompt: Prompt,
+    project_replace_with_prompt: Prompt,
+    project_replace: Option<ProjectReplace>,
+    project_replace_menu: Menu,
+    report_menu: Menu,
+    open_prompt: Prompt,
     save_prompt: Prompt,
     overwrite_prompt: Prompt,
+    buffer_menu: Menu,
+    time_prompt: Prompt,
+    history_menu: Menu,
+    history_nodes: Vec<usize>,
+    history_origin: (usize, View),
+    indentation_prompt: Prompt,
+    line_ending_prompt: Prompt,
+    encoding_prompt: Prompt,
+    save_encoding: Option<Encoding>, // Requested with Alt + S, only kept by the buffer once saved
 }
 
-pub fn run<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
+pub fn run<B: Backend + io::Write>(terminal: &mut Terminal<B>, file: File) -> io::Result<()> {
     let poll_duration = Duration::from_millis(500);
+    let busy_poll_duration = Duration::from_millis(50);
+    let config = Config::load();
     let mut data = Data {
         state: State::Editing,
-        file: File::new(),
+        buffers: Buffers::new(file, config.indentation, config.insert_final_newline),
+        windows: Windows::new(0),
+        message: None,
+        registers: Registers::new(config.osc52_clipboard),
+        search_prompt: Prompt::new("Search"),
+        search_origin: (View::default(), (0, 0)),
+        search_options: SearchOptions::new(),
+        replace_prompt: Prompt::new("Replace"),
+        replace_with_prompt: Prompt::new("Replace with"),
+        replace_session: None,
+        grep_prompt: Prompt::new("Search project"),
+        grep: None,
+        grep_menu: Menu::new("Project search"),
+        project_replace_prompt: Prompt::new("Replace in project"),
+        project_replace_with_prompt: Prompt::new("Replace in project with"),
+        project_replace: None,
+        project_replace_menu: Menu::new("Project replace preview (Space: toggle, Enter: write accepted changes)"),
+        report_menu: Menu::new("Project replace report"),
+        open_prompt: Prompt::new("Enter file path to open"),
         save_prompt: Prompt::new("Enter file path"),
         overwrite_prompt: Prompt::new("Do you want to overwrite? (y/N)"),
+        buffer_menu: Menu::new("Buffers"),
+        time_prompt: Prompt::new("Go back in time (e.g. 30s, 5m, 1h)"),
+        history_menu: Menu::new("Undo history"),
+        history_nodes: Vec::new(),
+        history_origin: (0, View::default()),
+        indentation_prompt: Prompt::new("Indentation (tabs, or spaces and a width, e.g. spaces 2)"),
+        line_ending_prompt: Prompt::new("Line ending (lf, crlf or cr)"),
+        encoding_prompt: Prompt::new("Encoding (utf-8, utf-8-bom, utf-16le(-bom), utf-16be(-bom), latin-1 or windows-1252)"),
+        save_encoding: None,
     };
 
     while data.state != State::Quitting {
         terminal.draw(|frame| render(frame, &mut data))?;
-        if event::poll(poll_duration)? {
+        // Background work is picked up between events, so poll more often while there is some
+        let busy = data.grep.as_ref().is_some_and(|grep| !grep.done) || data.buffers.iter().any(|file| file.is_indexing());
+        if event::poll(if busy { busy_poll_duration } else { poll_duration })? {
             handle_event(event::read()?, &mut data);
         }
+        update(&mut data);
+
+        if let Some(text) = data.registers.take_system_copy() {
+            execute!(terminal.backend_mut(), SetClipboard(text))?;
+        }
     }
 
     return Ok(());
@@ -0,0 +1,117 @@
+use std::{fs, path::PathBuf};
+
+use super::file::{File, Indentation};
+
+pub struct Buffers {
+    files: Vec<File>,
+    active_idx: usize,
+    indentation: Indentation, // Default for buffers whose indentation cannot be detected
+    insert_final_newline: bool, // Default for buffers without an `.editorconfig` setting
+}
+
+impl Buffers {
+    pub fn new(file: File, indentation: Indentation, insert_final_newline: bool) -> Self {
+        let mut buffers = Self {
+            files: Vec::new(),
+            active_idx: 0,
+            indentation,
+            insert_final_newline,
+        };
+        buffers.files.push(buffers.with_defaults(file));
+        return buffers;
+    }
+
+    pub fn active(&self) -> &File {
+        return &self.files[self.active_idx];
+    }
+
+    pub fn active_mut(&mut self) -> &mut File {
+        return &mut self.files[self.active_idx];
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> &mut File {
+        return &mut self.files[idx];
+    }
+
+    pub fn active_idx(&self) -> usize {
+        return self.active_idx;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.files.len();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &File> {
+        return self.files.iter();
+    }
+
+    // Functionality
+
+    /// Applies the defaults to settings that are not decided by the file itself.
+    fn with_defaults(&self, mut file: File) -> File {
+        file.detect_indentation(self.indentation);
+        file.set_default_insert_final_newline(self.insert_final_newline);
+        return file;
+    }
+
+    pub fn open(&mut self, file: File) {
+        let file = self.with_defaults(file);
+        if let Some(idx) = self.files.iter().position(|open_file| !file.path.as_str().is_empty() && open_file.path == file.path) {
+            self.active_idx = idx;
+        } else if self.active().is_blank() {
+            *self.active_mut() = file;
+        } else {
+            self.files.push(file);
+            self.active_idx = self.files.len() - 1;
+        }
+    }
+
+    /// Replaces the active buffer with `file`, e.g. the same file reopened with another encoding.
+    pub fn replace_active(&mut self, file: File) {
+        *self.active_mut() = self.with_defaults(file);
+    }
+
+    /// Reopens the buffers of the files at `paths`, e.g. after they were changed on disk, keeping their views.
+    pub fn reload(&mut self, paths: &[PathBuf]) {
+        let paths: Vec<PathBuf> = paths.iter().filter_map(|path| fs::canonicalize(path).ok()).collect();
+        for idx in 0..self.files.len() {
+            let path = String::from(self.files[idx].path.as_str());
+            if !fs::canonicalize(&path).is_ok_and(|path| paths.contains(&path)) {
+                continue;
+            }
+
+            if let Ok(file) = File::open(&path) {
+                let view = self.files[idx].view();
+                self.files[idx] = self.with_defaults(file);
+                self.files[idx].set_view(view);
+            }
+        }
+    }
+
+    pub fn close_active(&mut self) {
+        self.files.remove(self.active_idx);
+        if self.files.is_empty() {
+            let mut file = File::new();
+            file.set_indentation(self.indentation);
+            file.set_default_insert_final_newline(self.insert_final_newline);
+            self.files.push(file);
+        }
+        if self.active_idx >= self.files.len() {
+            self.active_idx = self.files.len() - 1;
+        }
+    }
+
+    pub fn select(&mut self, idx: usize) {
+        if idx < self.files.len() {
+            self.active_idx = idx;
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        self.active_idx = (self.active_idx + 1) % self.files.len();
+    }
+
+    pub fn select_previous(&mut self) {
+        self.active_idx = (self.active_idx + self.files.len() - 1) % self.files.len();
+    }
+}
@@ -0,0 +1,101 @@
+use std::{env, fs, path::PathBuf};
+
+use super::file::Indentation;
+
+/// User preferences, read from `$XDG_CONFIG_HOME/mate/config` as `key = value` lines.
+pub struct Config {
+    pub osc52_clipboard: bool,
+    pub indentation: Indentation, // Default for new and opened buffers
+    pub insert_final_newline: bool, // Always end saved files with a line break
+    indent_width_set: bool, // Otherwise the indent width follows the tab width
+}
+
+impl Config {
+    pub fn new() -> Self {
+        return Self {
+            osc52_clipboard: false,
+            indentation: Indentation::new(),
+            insert_final_newline: false,
+            indent_width_set: false,
+        };
+    }
+
+    fn path() -> Option<PathBuf> {
+        if let Some(config_home) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            return Some(PathBuf::from(config_home).join("mate").join("config"));
+        }
+        let home = env::var_os("HOME").filter(|dir| !dir.is_empty())?;
+        return Some(PathBuf::from(home).join(".config").join("mate").join("config"));
+    }
+
+    /// Loads the config file, keeping the defaults for anything missing or invalid.
+    pub fn load() -> Self {
+        let mut config = Self::new();
+        let content = match Self::path().and_then(|path| fs::read_to_string(path).ok()) {
+            Some(content) => content,
+            None => return config,
+        };
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                config.set(key.trim(), value.trim());
+            }
+        }
+
+        return config;
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        if key == "osc52_clipboard" {
+            if let Ok(value) = value.parse() {
+                self.osc52_clipboard = value;
+            }
+        } else if key == "expand_tab" {
+            if let Ok(value) = value.parse() {
+                self.indentation.expand_tab = value;
+            }
+        } else if key == "tab_width" {
+            if let Ok(value) = value.parse::<usize>() {
+                self.indentation.tab_width = value.clamp(1, 16);
+                if !self.indent_width_set {
+                    self.indentation.width = self.indentation.tab_width;
+                }
+            }
+        } else if key == "insert_final_newline" {
+            if let Ok(value) = value.parse() {
+                self.insert_final_newline = value;
+            }
+        } else if key == "indent_width" {
+            if let Ok(value) = value.parse::<usize>() {
+                self.indentation.width = value.clamp(1, 16);
+                self.indent_width_set = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indent_width_does_not_depend_on_line_order() {
+        for lines in [[("tab_width", "8"), ("indent_width", "2")], [("indent_width", "2"), ("tab_width", "8")]] {
+            let mut config = Config::new();
+            for (key, value) in lines {
+                config.set(key, value);
+            }
+            assert_eq!(config.indentation.tab_width, 8);
+            assert_eq!(config.indentation.width, 2);
+        }
+
+        let mut config = Config::new();
+        config.set("tab_width", "8");
+        assert_eq!(config.indentation.width, 8);
+    }
+}
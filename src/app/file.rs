@@ -1,179 +1,744 @@
-use tui::{layout::Rect, Frame, backend::Backend, text::{Spans, Text}, widgets::Paragraph};
+use std::{fmt, fs, io, ops::Range, path::Path, time::{Duration, SystemTime, UNIX_EPOCH}};
 
-use crate::app::unicode::UnicodeString;
+use tui::{layout::Rect, Frame, backend::Backend, text::{Span, Spans}, widgets::Paragraph, style::{Style, Modifier, Color}};
+
+use crate::app::unicode::{self, UnicodeString};
+
+use self::{editorconfig::EditorConfig, format::Format, history::{History, Edit, Change}, search::Search, text::Text};
+
+pub use self::{format::{Encoding, LineEnding}, indent::Indentation, replace::ReplaceSession, search::SearchOptions};
+
+mod editorconfig;
+mod format;
+mod history;
+mod indent;
+mod mapped;
+mod replace;
+mod search;
+mod text;
+mod undo_store;
+
+/// Files at least this large are memory-mapped instead of read into memory.
+const LARGE_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Large files are only searched this many lines around the cursor, as they are too big to scan after every edit.
+const LARGE_SEARCH_LINES: usize = 10_000;
+
+#[derive(Clone, Copy, Default)]
+pub struct View {
+    local_cursor: (usize, usize),
+    selection_anchor: Option<(usize, usize)>,
+    viewport_offset: (usize, usize), // Display columns and lines
+}
 
 pub struct File {
     pub path: UnicodeString,
-    lines: Vec<UnicodeString>,
+    text: Text,
     local_cursor: (usize, usize),
-    viewport_offset: (usize, usize),
+    selection_anchor: Option<(usize, usize)>,
+    viewport_offset: (usize, usize), // Display columns and lines
+    page_height: usize,
+    indentation: Indentation,
+    format: Format,
+    mixed_line_endings: bool,
+    history: History,
+    revision: u64, // Incremented on every edit
+    search: Option<Search>,
 }
 
 impl File {
     pub fn new() -> Self {
         return Self {
             path: UnicodeString::new(),
-            lines: vec![UnicodeString::new()],
+            text: Text::new(),
             local_cursor: (0, 0),
+            selection_anchor: None,
             viewport_offset: (0, 0),
+            page_height: 1,
+            indentation: Indentation::new(),
+            format: Format::new(),
+            mixed_line_endings: false,
+            history: History::new(),
+            revision: 0,
+            search: None,
         };
     }
 
+    pub fn open(path: &str) -> io::Result<Self> {
+        return Self::open_with_encoding(path, None);
+    }
+
+    /// Opens a file, decoding it with `encoding` if given. Otherwise the encoding comes from `.editorconfig` or is
+    /// detected from the content.
+    pub fn open_with_encoding(path: &str, encoding: Option<Encoding>) -> io::Result<Self> {
+        let mut file = Self::new();
+        file.path = UnicodeString::from(path);
+
+        if fs::metadata(path).is_ok_and(|metadata| metadata.len() >= LARGE_FILE_SIZE) {
+            if encoding.is_some_and(|encoding| encoding != Encoding::Utf8) {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "large files can only be opened as UTF-8"));
+            }
+
+            // Unedited lines are written back as they are, and edited ones with the line ending of the first line
+            file.text = Text::open_mapped(path)?;
+            if file.text.is_crlf() {
+                file.format.line_ending = LineEnding::Crlf;
+            }
+            return Ok(file);
+        }
+
+        let editorconfig = EditorConfig::find(Path::new(path));
+        let configured = encoding.or(editorconfig.charset());
+        match fs::read(path) {
+            Ok(bytes) => {
+                // A configured encoding keeps the byte order mark the file has, or does not have
+                file.format.encoding = configured.map_or_else(|| Encoding::detect(&bytes), |encoding| encoding.with_bom_of(&bytes));
+                let content = match file.format.encoding.decode(&bytes) {
+                    Ok(content) => content,
+                    // A wrong charset in `.editorconfig` should not keep the file from opening
+                    Err(_) if encoding.is_none() && configured.is_some() => {
+                        file.format.encoding = Encoding::detect(&bytes);
+                        file.format.encoding.decode(&bytes)?
+                    },
+                    Err(error) => return Err(error),
+                };
+                if let Some((line_ending, mixed)) = LineEnding::detect(&content) {
+                    file.format.line_ending = line_ending;
+                    file.mixed_line_endings = mixed;
+                }
+                // The final line break is written back on save, instead of showing as an empty last line
+                let normalized = LineEnding::normalize(&content);
+                let text = normalized.strip_suffix('\n');
+                file.format.final_newline = text.is_some();
+                file.text = Text::from(text.unwrap_or(&normalized));
+
+                // The history is keyed by the content on disk, which is what it is stored with on save
+                if let Some(history) = undo_store::load(Path::new(path), &content) {
+                    file.history = history;
+                }
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                file.format.encoding = configured.unwrap_or(Encoding::Utf8); // Will be created on save
+            },
+            Err(error) => return Err(error),
+        }
+
+        // Takes priority over the detected line ending, but not over the encoding the file was decoded with
+        let encoding = file.format.encoding;
+        editorconfig.apply_format(&mut file.format);
+        file.format.encoding = encoding;
+        return Ok(file);
+    }
+
+    pub fn write_to(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        return self.text.write_to(writer);
+    }
+
+    /// Whether the file is memory-mapped, in which case it must be saved without building its whole content.
+    pub fn is_large(&self) -> bool {
+        return self.text.is_mapped();
+    }
+
+    pub fn is_indexing(&self) -> bool {
+        return self.text.is_indexing();
+    }
+
+    /// Describes the large-file mode, e.g. "large file, indexing 1200 lines".
+    pub fn large_file_status(&self) -> Option<String> {
+        if self.text.is_indexing() {
+            return Some(format!("large file, indexing {} lines", self.text.line_count()));
+        } else if self.text.is_mapped() {
+            return Some(format!("large file, {} lines", self.text.line_count()));
+        }
+        return None;
+    }
+
+    /// Stores the undo history so that it is restored when the file is reopened with `content`.
+    pub fn save_history(&self, path: &Path, content: &str) -> io::Result<()> {
+        return undo_store::save(path, content, &self.history);
+    }
+
     // Miscellaneous
 
-    fn get_line_mut(&mut self) -> &mut UnicodeString {
-        return self.lines.get_mut(self.local_cursor.1).expect("should never index outside of file lines");
+    fn line_length(&self) -> usize {
+        return self.text.line_length(self.local_cursor.1);
     }
     
-    fn get_line(&self) -> &UnicodeString {
-        return self.lines.get(self.local_cursor.1).expect("should never index outside of file lines");
+    pub fn is_blank(&self) -> bool {
+        return self.path.as_str().is_empty() && self.text.line_count() == 1 && self.text.line_length(0) == 0;
     }
-    
+
+    pub fn display_name(&self) -> &str {
+        if self.path.as_str().is_empty() {
+            return "[No Name]";
+        } else {
+            return self.path.as_str();
+        }
+    }
+
+    pub fn view(&self) -> View {
+        return View {
+            local_cursor: self.local_cursor,
+            selection_anchor: self.selection_anchor,
+            viewport_offset: self.viewport_offset,
+        };
+    }
+
+    pub fn set_view(&mut self, view: View) {
+        // The lines may have changed since the view was stored, e.g. by another pane
+        self.local_cursor = (view.local_cursor.0, view.local_cursor.1.min(self.text.line_count() - 1));
+        self.selection_anchor = view.selection_anchor.map(|anchor| self.clamped_position(anchor));
+        self.viewport_offset = view.viewport_offset;
+    }
+
     fn clamped_file_cursor(&self) -> (usize, usize) {
-        let line = self.get_line();
-        if self.local_cursor.0 > line.length() {
-            return (line.length(), self.local_cursor.1);
+        let line_length = self.line_length();
+        if self.local_cursor.0 > line_length {
+            return (line_length, self.local_cursor.1);
         } else {
             return self.local_cursor;
         }
     }
 
-    pub fn to_string(&self) -> String {
-        let mut output = String::new();
-        for (i, line) in self.lines.iter().enumerate() {
-            output.push_str(line.as_str());
-            if i < self.lines.len() - 1 {
-                output.push('\n');
-            }
+    fn clamped_position(&self, position: (usize, usize)) -> (usize, usize) {
+        let line_idx = position.1.min(self.text.line_count() - 1);
+        return (position.0.min(self.text.line_length(line_idx)), line_idx);
+    }
+
+    /// Returns the start and end of the selection, if anything is selected.
+    pub fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.clamped_position(self.selection_anchor?);
+        let cursor = self.clamped_file_cursor();
+
+        // Positions are (char, line), so swap them to compare line first
+        if (anchor.1, anchor.0) < (cursor.1, cursor.0) {
+            return Some((anchor, cursor));
+        } else if anchor != cursor {
+            return Some((cursor, anchor));
+        } else {
+            return None;
         }
-        return output;
     }
-    
+
+    pub fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        return Some(self.text.slice(start, end));
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        return self.clamped_file_cursor();
+    }
+
+    pub fn set_cursor(&mut self, char_idx: usize, line_idx: usize) {
+        self.selection_anchor = None;
+        self.local_cursor.1 = line_idx.min(self.text.line_count() - 1);
+        self.local_cursor.0 = char_idx.min(self.line_length());
+    }
+
     // Functionality
-    
+
+    fn record(&mut self, edit: Edit, cursor_before: (usize, usize), cursor_after: (usize, usize), coalesce: bool) {
+        self.revision += 1;
+        self.history.record(edit, cursor_before, cursor_after, coalesce);
+    }
+
+    fn apply_edit(&mut self, edit: &Edit) {
+        self.revision += 1;
+        match edit {
+            Edit::Insert { at, text } => { self.text.insert(*at, text); },
+            Edit::Remove { at, text } => { self.text.remove(*at, Edit::text_end(*at, text)); },
+        }
+    }
+
+    fn undo_change(&mut self, change: &Change) {
+        for edit in change.edits.iter().rev() {
+            self.apply_edit(&edit.reversed());
+        }
+        self.set_cursor(change.cursor_before.0, change.cursor_before.1);
+    }
+
+    fn redo_change(&mut self, change: &Change) {
+        for edit in &change.edits {
+            self.apply_edit(edit);
+        }
+        self.set_cursor(change.cursor_after.0, change.cursor_after.1);
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(change) = self.history.undo() {
+            self.undo_change(&change);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(change) = self.history.redo() {
+            self.redo_change(&change);
+        }
+    }
+
+    /// Restores the buffer to how it was at the given history node, switching branches if needed.
+    pub fn goto_history(&mut self, node: usize) {
+        let (undo_changes, redo_changes) = self.history.path_to(node);
+        for change in &undo_changes {
+            self.undo_change(change);
+        }
+        for change in &redo_changes {
+            self.redo_change(change);
+        }
+    }
+
+    /// Moves to the previous state in time, regardless of which branch it is on.
+    pub fn history_earlier(&mut self) {
+        if self.history.current() > 0 {
+            self.goto_history(self.history.current() - 1);
+        }
+    }
+
+    /// Moves to the next state in time, regardless of which branch it is on.
+    pub fn history_later(&mut self) {
+        if self.history.current() + 1 < self.history.len() {
+            self.goto_history(self.history.current() + 1);
+        }
+    }
+
+    pub fn goto_history_ago(&mut self, duration: Duration) {
+        let time = SystemTime::now().checked_sub(duration).unwrap_or(UNIX_EPOCH);
+        let node = self.history.node_at(time);
+        self.goto_history(node);
+    }
+
+    pub fn history_node(&self) -> usize {
+        return self.history.current();
+    }
+
+    pub fn history_tree(&self) -> Vec<(usize, String)> {
+        return self.history.tree_lines();
+    }
+
+    // Search
+
+    /// The lines around `line_idx` that are searched in large files, or `None` to search the whole text.
+    fn search_window(&self, line_idx: usize) -> Option<Range<usize>> {
+        if !self.is_large() {
+            return None;
+        }
+        let start = line_idx.saturating_sub(LARGE_SEARCH_LINES / 2);
+        return Some(start..(start + LARGE_SEARCH_LINES).min(self.text.line_count()));
+    }
+
+    fn new_search(&self, pattern: &str, options: SearchOptions, line_idx: usize) -> Search {
+        return Search::new(pattern, options, &self.text, self.revision, self.search_window(line_idx));
+    }
+
+    fn refresh_search(&mut self) {
+        let cursor_line = self.clamped_file_cursor().1;
+        if let Some(search) = &self.search {
+            // The window moves along once the cursor comes within a quarter of its size of an edge that is not the
+            // start or end of the text
+            let margin = LARGE_SEARCH_LINES / 4;
+            let near_edge = search.window.as_ref().is_some_and(|window| {
+                (window.start > 0 && cursor_line < window.start + margin) || (window.end < self.text.line_count() && cursor_line + margin >= window.end)
+            });
+            if search.revision != self.revision || near_edge {
+                self.search = Some(self.new_search(&search.pattern.clone(), search.options, cursor_line));
+            }
+        }
+    }
+
+    fn goto_match(&mut self, match_idx: Option<usize>) -> bool {
+        let found = match_idx.and_then(|idx| self.search.as_ref().map(|search| search.matches[idx]));
+        if let Some(found) = found {
+            self.history.seal();
+            self.set_cursor(found.start, found.line);
+        }
+        return found.is_some();
+    }
+
+    /// Searches for `pattern` and moves to the first match at or after `from`. Returns whether there was a match.
+    pub fn search(&mut self, pattern: &str, options: SearchOptions, from: (usize, usize)) -> bool {
+        let search = self.new_search(pattern, options, from.1);
+        let match_idx = search.next_from(from, true);
+        self.search = Some(search);
+        return self.goto_match(match_idx);
+    }
+
+    pub fn search_next(&mut self) {
+        self.refresh_search();
+        let match_idx = self.search.as_ref().and_then(|search| search.next_from(self.clamped_file_cursor(), false));
+        self.goto_match(match_idx);
+    }
+
+    pub fn search_previous(&mut self) {
+        self.refresh_search();
+        let match_idx = self.search.as_ref().and_then(|search| search.previous_from(self.clamped_file_cursor()));
+        self.goto_match(match_idx);
+    }
+
+    pub fn clear_search(&mut self) {
+        self.search = None;
+    }
+
+    /// Describes the matches of the current search, e.g. "match 3 of 17".
+    pub fn search_status(&self) -> Option<String> {
+        let search = self.search.as_ref()?;
+        if search.pattern.is_empty() {
+            return None;
+        } else if !search.is_valid() {
+            return Some(String::from("invalid regex"));
+        }
+
+        let match_idx = search.match_at(self.clamped_file_cursor());
+        let status = match (match_idx, search.matches.len()) {
+            (_, 0) => String::from("no matches"),
+            (Some(idx), count) => format!("match {} of {}", idx + 1, count),
+            (None, count) => format!("{} matches", count),
+        };
+        return Some(if search.window.is_some() { format!("{} nearby", status) } else { status });
+    }
+
+    // Editing
+
+    /// Removes the selected text as the start of a new change. Returns whether anything was selected.
+    fn remove_selection(&mut self) -> bool {
+        let selection = self.selection();
+        self.selection_anchor = None;
+
+        if let Some((start, end)) = selection {
+            let cursor_before = self.clamped_file_cursor();
+            let text = self.text.remove(start, end);
+            self.local_cursor = start;
+
+            // Coalescing lets whatever replaces the selection be undone together with its removal
+            self.history.seal();
+            self.record(Edit::Remove { at: start, text }, cursor_before, start, true);
+            return true;
+        }
+        return false;
+    }
+
+    /// Removes the selection and returns the removed text.
+    pub fn cut_selection(&mut self) -> Option<String> {
+        let text = self.selected_text()?;
+        self.remove_selection();
+        self.history.seal();
+        return Some(text);
+    }
+
+    /// Inserts `text`, which may span several lines, in place of the selection as a single change.
+    pub fn insert_str(&mut self, text: &str) {
+        let replaced_selection = self.remove_selection();
+        if text.is_empty() {
+            self.history.seal();
+            return;
+        }
+
+        self.local_cursor = self.clamped_file_cursor();
+        let cursor_before = self.local_cursor;
+        self.local_cursor = self.text.insert(cursor_before, text);
+
+        self.record(Edit::Insert { at: cursor_before, text: String::from(text) }, cursor_before, self.local_cursor, replaced_selection);
+        self.history.seal();
+    }
+
     pub fn write_character(&mut self, ch: char) {
+        self.remove_selection();
         self.local_cursor = self.clamped_file_cursor();
+        let cursor_before = self.local_cursor;
     
-        let char_idx = self.local_cursor.0;
-        let line = self.get_line_mut();
-        line.insert(char_idx, ch);
-        self.local_cursor.0 += 1;
+        self.local_cursor = self.text.insert(cursor_before, ch.encode_utf8(&mut [0; 4]));
+
+        self.record(Edit::Insert { at: cursor_before, text: String::from(ch) }, cursor_before, self.local_cursor, true);
     }
     
     pub fn remove_character_before(&mut self) {
+        if self.remove_selection() {
+            self.history.seal();
+            return;
+        }
         self.local_cursor = self.clamped_file_cursor();
+        let cursor_before = self.local_cursor;
     
         if self.local_cursor.0 > 0 {
-            let char_idx = self.local_cursor.0 - 1;
-            let line = self.get_line_mut();
-            line.remove(char_idx);
-            self.local_cursor.0 -= 1;
+            self.local_cursor.0 = unicode::previous_grapheme(&self.text.line(self.local_cursor.1), self.local_cursor.0);
         } else if self.local_cursor.1 > 0 {
-            let curr_line = self.lines.remove(self.local_cursor.1);
             self.local_cursor.1 -= 1;
-            let prev_line = self.get_line_mut();
-            prev_line.push_str(curr_line.as_str());
-            self.local_cursor.0 = prev_line.length() - curr_line.length();
+            self.local_cursor.0 = self.line_length();
+        } else {
+            return;
         }
+
+        let text = self.text.remove(self.local_cursor, cursor_before);
+        self.record(Edit::Remove { at: self.local_cursor, text }, cursor_before, self.local_cursor, false);
     }
     
     pub fn remove_character_after(&mut self) {
+        if self.remove_selection() {
+            self.history.seal();
+            return;
+        }
         self.local_cursor = self.clamped_file_cursor();
     
-        if self.local_cursor.0 < self.get_line().length() {
-            let char_idx = self.local_cursor.0;
-            let line = self.get_line_mut();
-            line.remove(char_idx);
-        } else if self.local_cursor.1 < self.lines.len() - 1 {
-            let next_line = self.lines.remove(self.local_cursor.1 + 1);
-            let curr_line = self.get_line_mut();
-            curr_line.push_str(next_line.as_str());
-        }
+        let end = if self.local_cursor.0 < self.line_length() {
+            (unicode::next_grapheme(&self.text.line(self.local_cursor.1), self.local_cursor.0), self.local_cursor.1)
+        } else if self.local_cursor.1 < self.text.line_count() - 1 {
+            (0, self.local_cursor.1 + 1)
+        } else {
+            return;
+        };
+
+        let text = self.text.remove(self.local_cursor, end);
+        self.record(Edit::Remove { at: self.local_cursor, text }, self.local_cursor, self.local_cursor, false);
     }
     
     pub fn break_line(&mut self) {
+        let replaced_selection = self.remove_selection();
         self.local_cursor = self.clamped_file_cursor();
-        let char_idx = self.local_cursor.0;
-    
-        let curr_line = self.get_line_mut();
-        let curr_line_suffix: String = curr_line.drain(char_idx, curr_line.length()).collect();
-        self.lines.insert(self.local_cursor.1 + 1, UnicodeString::from(curr_line_suffix.as_str()));
-    
-        self.local_cursor.0 = 0;
-        self.local_cursor.1 += 1;
+        let cursor_before = self.local_cursor;
+        self.local_cursor = self.text.insert(cursor_before, "\n");
+
+        self.record(Edit::Insert { at: cursor_before, text: String::from('\n') }, cursor_before, self.local_cursor, replaced_selection);
+        self.history.seal();
     }
     
-    pub fn move_cursor_up(&mut self) {
+    /// Starts or keeps extending the selection if `select` is set, otherwise clears it.
+    fn update_selection(&mut self, select: bool) {
+        self.history.seal();
+        if !select {
+            self.selection_anchor = None;
+        } else if self.selection_anchor.is_none() {
+            self.selection_anchor = Some(self.clamped_file_cursor());
+        }
+    }
+
+    /// Moves the cursor to another line, keeping it in the same display column.
+    fn move_cursor_to_line(&mut self, line_idx: usize) {
+        let tab_width = self.indentation.tab_width;
+        let column = unicode::column(&self.text.line(self.local_cursor.1), self.local_cursor.0, tab_width);
+        self.local_cursor = (unicode::char_at_column(&self.text.line(line_idx), column, tab_width), line_idx);
+    }
+
+    pub fn move_cursor_up(&mut self, select: bool) {
+        self.update_selection(select);
         if self.local_cursor.1 > 0 {
-            self.local_cursor.1 -= 1;
+            self.move_cursor_to_line(self.local_cursor.1 - 1);
         }
     }
     
-    pub fn move_cursor_down(&mut self) {
-        if self.local_cursor.1 < self.lines.len() - 1 {
-            self.local_cursor.1 += 1;
+    pub fn move_cursor_down(&mut self, select: bool) {
+        self.update_selection(select);
+        if self.local_cursor.1 < self.text.line_count() - 1 {
+            self.move_cursor_to_line(self.local_cursor.1 + 1);
         }
     }
     
-    pub fn move_cursor_left(&mut self) {
+    pub fn move_cursor_left(&mut self, select: bool) {
+        self.update_selection(select);
         self.local_cursor = self.clamped_file_cursor();
         if self.local_cursor.0 > 0 {
-            self.local_cursor.0 -= 1;
+            self.local_cursor.0 = unicode::previous_grapheme(&self.text.line(self.local_cursor.1), self.local_cursor.0);
         }
     }
     
-    pub fn move_cursor_right(&mut self) {
+    pub fn move_cursor_right(&mut self, select: bool) {
+        self.update_selection(select);
         self.local_cursor = self.clamped_file_cursor();
-        if self.local_cursor.0 < self.get_line().length() {
-            self.local_cursor.0 += 1;
+        if self.local_cursor.0 < self.line_length() {
+            self.local_cursor.0 = unicode::next_grapheme(&self.text.line(self.local_cursor.1), self.local_cursor.0);
         }
     }
+
+    pub fn move_cursor_home(&mut self, select: bool) {
+        self.update_selection(select);
+        self.local_cursor.0 = 0;
+    }
+
+    pub fn move_cursor_end(&mut self, select: bool) {
+        self.update_selection(select);
+        self.local_cursor.0 = self.line_length();
+    }
+
+    pub fn move_cursor_page_up(&mut self, select: bool) {
+        self.update_selection(select);
+        self.move_cursor_to_line(self.local_cursor.1.saturating_sub(self.page_height));
+    }
+
+    pub fn move_cursor_page_down(&mut self, select: bool) {
+        self.update_selection(select);
+        self.move_cursor_to_line((self.local_cursor.1 + self.page_height).min(self.text.line_count() - 1));
+    }
     
     // Rendering
     
     pub fn adjust_viewport(&mut self, rect: Rect) {
-        if self.local_cursor.0 < self.viewport_offset.0 {
-            let diff = self.viewport_offset.0 - self.local_cursor.0;
-            self.viewport_offset.0 -= diff;
-        } else if self.local_cursor.0 - self.viewport_offset.0 > (rect.width - 1) as usize {
-            let diff = (self.local_cursor.0 - self.viewport_offset.0) - (rect.width - 1) as usize;
-            self.viewport_offset.0 += diff;
+        self.refresh_search();
+        self.page_height = rect.height.max(1) as usize;
+
+        // The whole char under the cursor has to be visible, which may be two columns wide
+        let cursor = self.clamped_file_cursor();
+        let line = self.text.line(cursor.1);
+        let tab_width = self.indentation.tab_width;
+        let cursor_column = unicode::column(&line, cursor.0, tab_width);
+        let cursor_width = (unicode::column(&line, unicode::next_grapheme(&line, cursor.0), tab_width) - cursor_column).max(1);
+
+        if cursor_column < self.viewport_offset.0 {
+            self.viewport_offset.0 = cursor_column;
+        } else if cursor_column + cursor_width > self.viewport_offset.0 + rect.width as usize {
+            self.viewport_offset.0 = (cursor_column + cursor_width).saturating_sub(rect.width as usize);
         }
     
         if self.local_cursor.1 < self.viewport_offset.1 {
             let diff = self.viewport_offset.1 - self.local_cursor.1;
             self.viewport_offset.1 -= diff;
-        } else if self.local_cursor.1 - self.viewport_offset.1 > (rect.height - 1) as usize {
-            let diff = (self.local_cursor.1 - self.viewport_offset.1) - (rect.height - 1) as usize;
+        } else if self.local_cursor.1 - self.viewport_offset.1 > rect.height.saturating_sub(1) as usize {
+            let diff = (self.local_cursor.1 - self.viewport_offset.1) - rect.height.saturating_sub(1) as usize;
             self.viewport_offset.1 += diff;
         } 
     }
     
     pub fn global_cursor(&self, rect: Rect) -> (u16, u16) {
         let file_cursor = self.clamped_file_cursor();
+        let cursor_column = unicode::column(&self.text.line(file_cursor.1), file_cursor.0, self.indentation.tab_width);
         return (
-            rect.x + (file_cursor.0 - self.viewport_offset.0) as u16,
+            rect.x + (cursor_column - self.viewport_offset.0) as u16,
             rect.y + (file_cursor.1 - self.viewport_offset.1) as u16,
         );
     }
     
     pub fn render<B: Backend>(&self, frame: &mut Frame<B>, rect: Rect) {
         let first_line_idx = self.viewport_offset.1;
-        let last_line_idx = if self.viewport_offset.1 + rect.height as usize > self.lines.len() {
-            self.lines.len()
+        let last_line_idx = if self.viewport_offset.1 + rect.height as usize > self.text.line_count() {
+            self.text.line_count()
         } else {
             self.viewport_offset.1 + rect.height as usize
         };
     
+        let selection = self.selection();
+        let selected_style = Style::default().add_modifier(Modifier::REVERSED);
+        let match_style = Style::default().fg(Color::Black).bg(Color::Yellow);
+        let current_match_style = Style::default().fg(Color::Black).bg(Color::LightRed);
+        let cursor = self.clamped_file_cursor();
+
         let mut spans_vec = Vec::new();
-        for line in &self.lines[first_line_idx..last_line_idx] {
-            if line.length() >= self.viewport_offset.0 {
-                spans_vec.push(Spans::from(&line[self.viewport_offset.0..]));
-            } else {
-                spans_vec.push(Spans::from(""));
+        for line_idx in first_line_idx..last_line_idx {
+            let line = self.text.line(line_idx);
+            let line_length = line.chars().count();
+
+            // Later highlights take priority over earlier ones
+            let mut highlights = Vec::new();
+            if let Some(search) = &self.search {
+                for m in search.on_line(line_idx) {
+                    let style = if (m.start, m.line) == cursor { current_match_style } else { match_style };
+                    highlights.push((m.start, m.end, style));
+                }
+            }
+
+            // Whether the selection continues onto the next line
+            let mut selects_line_break = false;
+            if let Some((start, end)) = selection.filter(|(start, end)| start.1 <= line_idx && line_idx <= end.1) {
+                let selected_start = if start.1 == line_idx { start.0 } else { 0 };
+                let selected_end = if end.1 == line_idx { end.0 } else { line_length };
+                highlights.push((selected_start, selected_end, selected_style));
+                selects_line_break = end.1 > line_idx;
+            }
+
+            // A wide char or tab cut off at the left edge is replaced by padding
+            let tab_width = self.indentation.tab_width;
+            let (visible_start, padding) = unicode::skip_columns(&line, self.viewport_offset.0, tab_width);
+            let mut span_vec = vec![Span::raw(" ".repeat(padding))];
+            span_vec.extend(highlighted_spans(&line, line_length, (visible_start, self.viewport_offset.0 + padding), tab_width, &highlights));
+            if selects_line_break {
+                span_vec.push(Span::styled(" ", selected_style));
             }
+            spans_vec.push(Spans::from(span_vec));
         }
     
-        frame.render_widget(Paragraph::new(Text::from(spans_vec)), rect);
+        frame.render_widget(Paragraph::new(spans_vec), rect);
     }
-}
\ No newline at end of file
+}
+
+/// Splits the part of `line` from `visible_start`, a char index and its display column, into spans styled by the
+/// `(start, end, style)` highlights. Tabs are expanded to spaces.
+fn highlighted_spans(
+    line: &str,
+    line_length: usize,
+    visible_start: (usize, usize),
+    tab_width: usize,
+    highlights: &[(usize, usize, Style)],
+) -> Vec<Span<'static>> {
+    let (visible_start, mut column) = visible_start;
+    let mut boundaries = vec![visible_start, line_length];
+    for (start, end, _) in highlights {
+        boundaries.push((*start).clamp(visible_start, line_length));
+        boundaries.push((*end).clamp(visible_start, line_length));
+    }
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let byte_indices: Vec<usize> = line.char_indices().map(|(byte_idx, _)| byte_idx).chain([line.len()]).collect();
+    let mut spans = Vec::new();
+    for segment in boundaries.windows(2) {
+        let style = highlights.iter().rev()
+            .find(|(start, end, _)| *start <= segment[0] && segment[0] < *end)
+            .map_or(Style::default(), |(_, _, style)| *style);
+        let text = unicode::expand_tabs(&line[byte_indices[segment[0]]..byte_indices[segment[1]]], column, tab_width);
+        column += unicode::width(&text);
+        spans.push(Span::styled(text, style));
+    }
+    return spans;
+}
+
+impl fmt::Display for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return self.text.fmt(f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY: &str = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+
+    fn file_with(text: &str) -> File {
+        let mut file = File::new();
+        file.insert_str(text);
+        file.set_cursor(0, 0);
+        return file;
+    }
+
+    #[test]
+    fn moves_by_grapheme() {
+        let mut file = file_with(&format!("e\u{301}{}\u{1F1E9}\u{1F1EA}", FAMILY));
+        let mut positions = vec![file.cursor().0];
+        for _ in 0..4 {
+            file.move_cursor_right(false);
+            positions.push(file.cursor().0);
+        }
+        assert_eq!(positions, vec![0, 2, 7, 9, 9]);
+
+        file.move_cursor_left(false);
+        file.move_cursor_left(false);
+        assert_eq!(file.cursor(), (2, 0));
+    }
+
+    #[test]
+    fn deletes_whole_graphemes() {
+        let mut file = file_with(&format!("a{}b", FAMILY));
+        file.set_cursor(6, 0);
+        file.remove_character_before();
+        assert_eq!(file.to_string(), "ab");
+        assert_eq!(file.cursor(), (1, 0));
+
+        file.undo();
+        file.set_cursor(1, 0);
+        file.remove_character_after();
+        assert_eq!(file.to_string(), "ab");
+    }
+
+    #[test]
+    fn selects_whole_graphemes() {
+        let mut file = file_with("n\u{303}o");
+        file.move_cursor_right(true);
+        assert_eq!(file.selected_text().as_deref(), Some("n\u{303}"));
+    }
+}
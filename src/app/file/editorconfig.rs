@@ -0,0 +1,338 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use regex::Regex;
+
+use super::{format::{Encoding, Format, LineEnding}, indent::Indentation};
+
+/// Ranges such as `{1..100}` are expanded into alternatives, up to this many numbers.
+const MAX_RANGE_SIZE: i64 = 1000;
+
+/// The properties of the `.editorconfig` files that apply to a file. `None` means unset.
+#[derive(Clone, Default)]
+pub struct EditorConfig {
+    indent_style: Option<bool>, // Whether to indent with spaces
+    indent_size: Option<Option<usize>>, // `Some(None)` means the tab width
+    tab_width: Option<usize>,
+    end_of_line: Option<LineEnding>,
+    charset: Option<Encoding>,
+    trim_trailing_whitespace: Option<bool>,
+    insert_final_newline: Option<bool>,
+}
+
+impl EditorConfig {
+    /// Finds the `.editorconfig` files from the directory of `path` up to the closest one marked as root, and
+    /// collects the properties of all sections matching `path`. Closer files take priority.
+    pub fn find(path: &Path) -> Self {
+        let mut config = Self::default();
+        let path = match absolute_path(path) {
+            Some(path) => path,
+            None => return config,
+        };
+
+        let mut files = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            if let Ok(content) = fs::read_to_string(dir.join(".editorconfig")) {
+                let is_root = parse_root(&content);
+                files.push((dir.to_path_buf(), content));
+                if is_root {
+                    break;
+                }
+            }
+        }
+
+        for (dir, content) in files.iter().rev() {
+            let relative_path = match path.strip_prefix(dir) {
+                Ok(relative_path) => relative_path.to_string_lossy().replace('\\', "/"),
+                Err(_) => continue,
+            };
+            config.apply_file(content, &relative_path);
+        }
+        return config;
+    }
+
+    fn apply_file(&mut self, content: &str, relative_path: &str) {
+        let mut matches = false;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(glob) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                matches = glob_regex(glob).is_some_and(|regex| regex.is_match(relative_path));
+            } else if let Some((key, value)) = line.split_once('=') {
+                if matches {
+                    self.set(&key.trim().to_lowercase(), &value.trim().to_lowercase());
+                }
+            }
+        }
+    }
+
+    /// Sets a property, where `unset` or an invalid value removes it.
+    fn set(&mut self, key: &str, value: &str) {
+        let parse_bool = || match value {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+        let parse_size = || value.parse::<usize>().ok().filter(|size| (1..=16).contains(size));
+
+        match key {
+            "indent_style" => self.indent_style = match value {
+                "space" => Some(true),
+                "tab" => Some(false),
+                _ => None,
+            },
+            "indent_size" => self.indent_size = if value == "tab" { Some(None) } else { parse_size().map(Some) },
+            "tab_width" => self.tab_width = parse_size(),
+            "end_of_line" => self.end_of_line = LineEnding::parse(value),
+            "charset" => self.charset = match value {
+                "utf-8" => Some(Encoding::Utf8),
+                "utf-8-bom" => Some(Encoding::Utf8Bom),
+                "utf-16le" => Some(Encoding::Utf16Le),
+                "utf-16be" => Some(Encoding::Utf16Be),
+                "latin1" => Some(Encoding::Latin1),
+                _ => None,
+            },
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = parse_bool(),
+            "insert_final_newline" => self.insert_final_newline = parse_bool(),
+            _ => (),
+        }
+    }
+
+    pub fn charset(&self) -> Option<Encoding> {
+        return self.charset;
+    }
+
+    pub fn apply_indentation(&self, indentation: &mut Indentation) {
+        // Without a tab width, tabs are as wide as a level of indentation
+        if let Some(tab_width) = self.tab_width.or(self.indent_size.flatten()) {
+            indentation.tab_width = tab_width;
+        }
+        if let Some(expand_tab) = self.indent_style {
+            indentation.expand_tab = expand_tab;
+            indentation.width = indentation.tab_width;
+        }
+        match self.indent_size {
+            Some(Some(width)) => indentation.width = width,
+            Some(None) => indentation.width = indentation.tab_width,
+            None => (),
+        }
+    }
+
+    pub fn apply_format(&self, format: &mut Format) {
+        if let Some(line_ending) = self.end_of_line {
+            format.line_ending = line_ending;
+        }
+        if let Some(encoding) = self.charset {
+            format.encoding = encoding;
+        }
+        if let Some(trim_trailing_whitespace) = self.trim_trailing_whitespace {
+            format.trim_trailing_whitespace = trim_trailing_whitespace;
+        }
+        if self.insert_final_newline.is_some() {
+            format.insert_final_newline = self.insert_final_newline;
+        }
+    }
+}
+
+fn absolute_path(path: &Path) -> Option<PathBuf> {
+    if let Ok(path) = fs::canonicalize(path) {
+        return Some(path);
+    }
+
+    // The file may not exist yet, but its directory should
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    return Some(fs::canonicalize(parent).ok()?.join(path.file_name()?));
+}
+
+/// Whether the preamble, before the first section, contains `root = true`.
+fn parse_root(content: &str) -> bool {
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            return false;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            if key.trim().eq_ignore_ascii_case("root") && value.trim().eq_ignore_ascii_case("true") {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+/// Converts a section glob into a regex matching paths relative to the `.editorconfig` file's directory.
+/// Globs without a '/' match file names in any subdirectory.
+fn glob_regex(glob: &str) -> Option<Regex> {
+    let glob = if glob.contains('/') { glob.strip_prefix('/').unwrap_or(glob).to_string() } else { format!("**/{}", glob) };
+    let chars: Vec<char> = glob.chars().collect();
+    return Regex::new(&format!("^{}$", glob_to_pattern(&chars))).ok();
+}
+
+fn glob_to_pattern(chars: &[char]) -> String {
+    let mut pattern = String::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        match chars[idx] {
+            '*' if chars.get(idx + 1) == Some(&'*') => {
+                // `**/` may also match no directories at all
+                if chars.get(idx + 2) == Some(&'/') {
+                    pattern.push_str("(?:.*/)?");
+                    idx += 1;
+                } else {
+                    pattern.push_str(".*");
+                }
+                idx += 1;
+            },
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '[' => match chars[idx + 1..].iter().position(|ch| *ch == ']') {
+                Some(length) => {
+                    let class: String = chars[idx + 1..idx + 1 + length].iter().collect();
+                    let class = match class.strip_prefix('!') {
+                        Some(class) => format!("^{}", class),
+                        None => class,
+                    };
+                    pattern.push_str(&format!("[{}]", class.replace('\\', "\\\\").replace('[', "\\[")));
+                    idx += length + 1;
+                },
+                None => pattern.push_str("\\["),
+            },
+            '{' => match closing_brace(&chars[idx..]) {
+                Some(length) => {
+                    pattern.push_str(&brace_pattern(&chars[idx + 1..idx + length]));
+                    idx += length;
+                },
+                None => pattern.push_str("\\{"),
+            },
+            '\\' if idx + 1 < chars.len() => {
+                pattern.push_str(&regex::escape(&chars[idx + 1].to_string()));
+                idx += 1;
+            },
+            ch => pattern.push_str(&regex::escape(&ch.to_string())),
+        }
+        idx += 1;
+    }
+    return pattern;
+}
+
+/// Returns the offset of the brace closing the one `chars` starts with.
+fn closing_brace(chars: &[char]) -> Option<usize> {
+    let mut depth = 0;
+    for (idx, ch) in chars.iter().enumerate() {
+        match ch {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            },
+            _ => (),
+        }
+    }
+    return None;
+}
+
+/// Converts the inside of `{a,b}` into alternatives, or of `{1..3}` into the numbers in that range.
+fn brace_pattern(chars: &[char]) -> String {
+    let inner: String = chars.iter().collect();
+    if let Some((start, end)) = inner.split_once("..") {
+        if let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) {
+            let (start, end) = (start.min(end), start.max(end));
+            if end.checked_sub(start).is_some_and(|size| size < MAX_RANGE_SIZE) {
+                let numbers: Vec<String> = (start..=end).map(|number| number.to_string()).collect();
+                return format!("(?:{})", numbers.join("|"));
+            }
+        }
+    }
+
+    let mut alternatives = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, ch) in chars.iter().enumerate() {
+        match ch {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(glob_to_pattern(&chars[start..idx]));
+                start = idx + 1;
+            },
+            _ => (),
+        }
+    }
+
+    // A single alternative is not a set, so the braces are literal
+    if alternatives.is_empty() {
+        return format!("\\{{{}\\}}", glob_to_pattern(chars));
+    }
+    alternatives.push(glob_to_pattern(&chars[start..]));
+    return format!("(?:{})", alternatives.join("|"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        return glob_regex(glob).unwrap().is_match(path);
+    }
+
+    #[test]
+    fn globs_without_slash_match_at_any_depth() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", "src/app/main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("Makefile", "sub/Makefile"));
+    }
+
+    #[test]
+    fn globs_with_slash_are_relative() {
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/app/main.rs"));
+        assert!(!matches("src/*.rs", "other/src/main.rs"));
+        assert!(matches("/src/**/*.rs", "src/app/main.rs"));
+        assert!(matches("src/**/*.rs", "src/main.rs"));
+    }
+
+    #[test]
+    fn braces_and_classes() {
+        assert!(matches("*.{js,ts}", "index.ts"));
+        assert!(!matches("*.{js,ts}", "index.rs"));
+        assert!(matches("file{1..3}.txt", "file2.txt"));
+        assert!(!matches("file{1..3}.txt", "file4.txt"));
+        assert!(!matches("{-9223372036854775808..9223372036854775807}", "1"));
+        assert!(matches("{single}", "{single}"));
+        assert!(matches("[ab].txt", "a.txt"));
+        assert!(!matches("[!ab].txt", "a.txt"));
+        assert!(matches("?.txt", "c.txt"));
+    }
+
+    #[test]
+    fn later_sections_override_and_unset_clears() {
+        let mut config = EditorConfig::default();
+        config.apply_file("root = true\n[*]\nindent_style = tab\nindent_size = 8\n[*.rs]\nindent_style = space\nindent_size = unset\n", "src/main.rs");
+
+        let mut indentation = Indentation::new();
+        indentation.tab_width = 8;
+        config.apply_indentation(&mut indentation);
+        assert!(indentation.expand_tab);
+        assert_eq!(indentation.width, 8);
+        assert!(config.indent_size.is_none());
+    }
+
+    #[test]
+    fn insert_final_newline_false_removes_it() {
+        let mut config = EditorConfig::default();
+        config.apply_file("[*]\ninsert_final_newline = false\n", "file.txt");
+
+        let mut format = Format { final_newline: true, ..Format::new() };
+        config.apply_format(&mut format);
+        assert!(!format.writes_final_newline());
+        assert_eq!(format.disk_text(String::from("a\nb")), "a\nb");
+
+        // Without the setting, the file keeps its final newline
+        let format = Format { final_newline: true, ..Format::new() };
+        assert_eq!(format.disk_text(String::from("a\nb")), "a\nb\n");
+    }
+}
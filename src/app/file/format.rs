@@ -0,0 +1,473 @@
+use std::{borrow::Cow, fs, io, path::Path};
+
+use super::{File, editorconfig::EditorConfig, history::Edit};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => return "\n",
+            LineEnding::Crlf => return "\r\n",
+            LineEnding::Cr => return "\r",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => return "LF",
+            LineEnding::Crlf => return "CRLF",
+            LineEnding::Cr => return "CR",
+        }
+    }
+
+    /// Parses a name such as "crlf", ignoring case.
+    pub fn parse(input: &str) -> Option<Self> {
+        match input.trim().to_lowercase().as_str() {
+            "lf" => return Some(LineEnding::Lf),
+            "crlf" => return Some(LineEnding::Crlf),
+            "cr" => return Some(LineEnding::Cr),
+            _ => return None,
+        }
+    }
+
+    /// Returns the most common line ending in `text` and whether it contains several kinds, or `None` if it has
+    /// no line breaks. Ties go to LF, then CRLF.
+    pub fn detect(text: &str) -> Option<(Self, bool)> {
+        let bytes = text.as_bytes();
+        let mut counts = [0; 3]; // LF, CRLF and CR
+        let mut idx = 0;
+        while idx < bytes.len() {
+            if bytes[idx] == b'\n' {
+                counts[0] += 1;
+            } else if bytes[idx] == b'\r' && bytes.get(idx + 1) == Some(&b'\n') {
+                counts[1] += 1;
+                idx += 1;
+            } else if bytes[idx] == b'\r' {
+                counts[2] += 1;
+            }
+            idx += 1;
+        }
+
+        let kinds = counts.iter().filter(|count| **count > 0).count();
+        if kinds == 0 {
+            return None;
+        }
+
+        // The last maximum is returned, so the order is reversed for ties to go to the first kinds
+        let line_ending = match counts.iter().enumerate().rev().max_by_key(|(_, count)| **count).map(|(idx, _)| idx) {
+            Some(1) => LineEnding::Crlf,
+            Some(2) => LineEnding::Cr,
+            _ => LineEnding::Lf,
+        };
+        return Some((line_ending, kinds > 1));
+    }
+
+    /// Converts every line break in `text` to '\n', which is the only one the buffer uses.
+    pub fn normalize(text: &str) -> Cow<'_, str> {
+        if !text.contains('\r') {
+            return Cow::from(text);
+        }
+        return Cow::from(text.replace("\r\n", "\n").replace('\r', "\n"));
+    }
+}
+
+/// The chars of bytes 0x80 to 0x9F in Windows-1252, which Latin-1 uses for control characters. The bytes that
+/// Windows-1252 leaves undefined keep their Latin-1 meaning.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// Only the start of a file is looked at to detect UTF-16 without a byte order mark.
+const DETECTION_BYTES: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16LeBom,
+    Utf16Be,
+    Utf16BeBom,
+    Latin1,
+    Windows1252,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => return "UTF-8",
+            Encoding::Utf8Bom => return "UTF-8 BOM",
+            Encoding::Utf16Le => return "UTF-16LE",
+            Encoding::Utf16LeBom => return "UTF-16LE BOM",
+            Encoding::Utf16Be => return "UTF-16BE",
+            Encoding::Utf16BeBom => return "UTF-16BE BOM",
+            Encoding::Latin1 => return "Latin-1",
+            Encoding::Windows1252 => return "Windows-1252",
+        }
+    }
+
+    /// Parses a name such as "utf-16le" or "cp1252", ignoring case and separators.
+    pub fn parse(input: &str) -> Option<Self> {
+        let name: String = input.chars().filter(|ch| ch.is_alphanumeric()).collect::<String>().to_lowercase();
+        match name.as_str() {
+            "utf8" => return Some(Encoding::Utf8),
+            "utf8bom" => return Some(Encoding::Utf8Bom),
+            "utf16le" => return Some(Encoding::Utf16Le),
+            "utf16lebom" => return Some(Encoding::Utf16LeBom),
+            "utf16be" => return Some(Encoding::Utf16Be),
+            "utf16bebom" => return Some(Encoding::Utf16BeBom),
+            "latin1" | "iso88591" => return Some(Encoding::Latin1),
+            "windows1252" | "cp1252" => return Some(Encoding::Windows1252),
+            _ => return None,
+        }
+    }
+
+    /// Works out the encoding from a byte order mark, from how many zero bytes there are for UTF-16 without one,
+    /// or from whether the bytes are valid UTF-8. Anything else is taken to be Windows-1252, which can decode
+    /// any bytes.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"\xEF\xBB\xBF") {
+            return Encoding::Utf8Bom;
+        } else if bytes.starts_with(b"\xFF\xFE") {
+            return Encoding::Utf16LeBom;
+        } else if bytes.starts_with(b"\xFE\xFF") {
+            return Encoding::Utf16BeBom;
+        }
+
+        // Text that is mostly ASCII has a zero byte in every other position in UTF-16
+        let sample = &bytes[..bytes.len().min(DETECTION_BYTES) / 2 * 2];
+        let zeros_at = |parity: usize| sample.iter().skip(parity).step_by(2).filter(|byte| **byte == 0).count();
+        let (even_zeros, odd_zeros) = (zeros_at(0), zeros_at(1));
+        let units = sample.len() / 2;
+        if units > 0 && even_zeros == 0 && odd_zeros > units / 4 && Encoding::Utf16Le.decode(bytes).is_ok() {
+            return Encoding::Utf16Le;
+        } else if units > 0 && odd_zeros == 0 && even_zeros > units / 4 && Encoding::Utf16Be.decode(bytes).is_ok() {
+            return Encoding::Utf16Be;
+        }
+
+        if std::str::from_utf8(bytes).is_ok() {
+            return Encoding::Utf8;
+        }
+        return Encoding::Windows1252;
+    }
+
+    /// The variant of this encoding with a byte order mark if `bytes` start with one, so that saving keeps it.
+    pub fn with_bom_of(self, bytes: &[u8]) -> Self {
+        match self {
+            Encoding::Utf8 if bytes.starts_with(b"\xEF\xBB\xBF") => return Encoding::Utf8Bom,
+            Encoding::Utf16Le if bytes.starts_with(b"\xFF\xFE") => return Encoding::Utf16LeBom,
+            Encoding::Utf16Be if bytes.starts_with(b"\xFE\xFF") => return Encoding::Utf16BeBom,
+            _ => return self,
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> io::Result<String> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        match self {
+            Encoding::Utf8 => return String::from_utf8(bytes.to_vec()).map_err(|_| invalid("file is not valid UTF-8")),
+            Encoding::Utf8Bom => {
+                let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+                return String::from_utf8(bytes.to_vec()).map_err(|_| invalid("file is not valid UTF-8"));
+            },
+            Encoding::Utf16Le | Encoding::Utf16LeBom | Encoding::Utf16Be | Encoding::Utf16BeBom => {
+                if !bytes.len().is_multiple_of(2) {
+                    return Err(invalid("file is not valid UTF-16"));
+                }
+                let mut units: Vec<u16> = bytes.chunks(2)
+                    .map(|pair| if self.is_little_endian() { u16::from_le_bytes([pair[0], pair[1]]) } else { u16::from_be_bytes([pair[0], pair[1]]) })
+                    .collect();
+                if units.first() == Some(&0xFEFF) {
+                    units.remove(0);
+                }
+                return String::from_utf16(&units).map_err(|_| invalid("file is not valid UTF-16"));
+            },
+            Encoding::Latin1 => return Ok(bytes.iter().map(|byte| char::from(*byte)).collect()),
+            Encoding::Windows1252 => {
+                return Ok(bytes.iter().map(|byte| match byte {
+                    0x80..=0x9F => WINDOWS_1252[(byte - 0x80) as usize],
+                    _ => char::from(*byte),
+                }).collect());
+            },
+        }
+    }
+
+    /// Whether `ch` can be represented, which is only not the case for single-byte encodings.
+    pub fn can_encode(&self, ch: char) -> bool {
+        match self {
+            Encoding::Latin1 | Encoding::Windows1252 => return self.encode_byte(ch).is_some(),
+            _ => return true,
+        }
+    }
+
+    /// Encodes `ch` as a single byte, for Latin-1 and Windows-1252.
+    fn encode_byte(&self, ch: char) -> Option<u8> {
+        if *self == Encoding::Windows1252 {
+            if let Some(idx) = WINDOWS_1252.iter().position(|mapped| *mapped == ch) {
+                return Some(0x80 + idx as u8);
+            }
+            return u8::try_from(ch).ok().filter(|byte| !(0x80..=0x9F).contains(byte));
+        }
+        return u8::try_from(ch).ok();
+    }
+
+    fn is_little_endian(&self) -> bool {
+        return matches!(self, Encoding::Utf16Le | Encoding::Utf16LeBom);
+    }
+
+    /// Fails on the first char that cannot be represented, rather than writing a replacement.
+    pub fn encode(&self, text: &str) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => return Ok(text.as_bytes().to_vec()),
+            Encoding::Utf8Bom => return Ok([b"\xEF\xBB\xBF", text.as_bytes()].concat()),
+            Encoding::Utf16Le | Encoding::Utf16LeBom | Encoding::Utf16Be | Encoding::Utf16BeBom => {
+                let bom = matches!(self, Encoding::Utf16LeBom | Encoding::Utf16BeBom).then_some(0xFEFF);
+                let units = bom.into_iter().chain(text.encode_utf16());
+                if self.is_little_endian() {
+                    return Ok(units.flat_map(|unit| unit.to_le_bytes()).collect());
+                }
+                return Ok(units.flat_map(|unit| unit.to_be_bytes()).collect());
+            },
+            Encoding::Latin1 | Encoding::Windows1252 => {
+                return text.chars()
+                    .map(|ch| self.encode_byte(ch).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, format!("'{}' cannot be encoded as {}", ch, self.name()))
+                    }))
+                    .collect();
+            },
+        }
+    }
+}
+
+/// How a buffer's text is written to disk.
+#[derive(Clone, Copy)]
+pub struct Format {
+    pub line_ending: LineEnding,
+    pub encoding: Encoding,
+    pub trim_trailing_whitespace: bool,
+    pub final_newline: bool, // Whether the last line ends with a line break, which is not part of the buffer
+    pub insert_final_newline: Option<bool>, // Whether to always or never write a final line break, if decided
+}
+
+impl Format {
+    pub fn new() -> Self {
+        return Self {
+            line_ending: LineEnding::Lf,
+            encoding: Encoding::Utf8,
+            trim_trailing_whitespace: false,
+            final_newline: false,
+            insert_final_newline: None,
+        };
+    }
+
+    /// Whether a final line break is written. The file keeps whether it had one, unless that was decided.
+    pub fn writes_final_newline(&self) -> bool {
+        return self.insert_final_newline.unwrap_or(self.final_newline);
+    }
+
+    /// Converts the buffer's text into what is written to disk, before encoding it.
+    pub fn disk_text(&self, mut text: String) -> String {
+        if self.writes_final_newline() {
+            text.push('\n');
+        }
+        if self.line_ending == LineEnding::Lf && !text.contains('\r') {
+            return text;
+        }
+
+        // Pasted text may contain other line breaks, which are converted as well
+        let lines: Vec<&str> = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+        return lines.join(self.line_ending.as_str());
+    }
+}
+
+impl File {
+    pub fn format(&self) -> Format {
+        return self.format;
+    }
+
+    /// Describes the line ending for the status bar, e.g. "CRLF", warning if the file mixed several kinds or will be
+    /// saved without a final newline.
+    pub fn line_ending_status(&self) -> String {
+        let mut notes = Vec::new();
+        if self.mixed_line_endings {
+            notes.push("mixed line endings");
+        }
+        if !self.is_large() && !self.format.writes_final_newline() {
+            notes.push("no final newline");
+        }
+
+        if notes.is_empty() {
+            return String::from(self.format.line_ending.name());
+        }
+        return format!("{} ({})", self.format.line_ending.name(), notes.join(", "));
+    }
+
+    /// Always writes a final newline if `insert` is set, unless `.editorconfig` decided it. Otherwise the buffer keeps
+    /// whether the file ended with one.
+    pub fn set_default_insert_final_newline(&mut self, insert: bool) {
+        if insert {
+            self.format.insert_final_newline.get_or_insert(true);
+        }
+    }
+
+    /// Switches whether the buffer is saved with a final newline, overriding the defaults. Returns the new setting,
+    /// or `None` for large files, which are written as they are on disk.
+    pub fn toggle_final_newline(&mut self) -> Option<bool> {
+        if self.is_large() {
+            return None;
+        }
+        let final_newline = !self.format.writes_final_newline();
+        self.format.final_newline = final_newline;
+        self.format.insert_final_newline = Some(final_newline);
+        return Some(final_newline);
+    }
+
+    /// Sets the line ending every line is written with on save. Large files are written as they are on disk.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) -> bool {
+        if self.is_large() {
+            return false;
+        }
+        self.format.line_ending = line_ending;
+        self.mixed_line_endings = false;
+        return true;
+    }
+
+    /// Sets the encoding the buffer is written with on save.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.format.encoding = encoding;
+    }
+
+    /// Whether saving the buffer would change its file on disk, e.g. because of unsaved changes. Large files are
+    /// always taken to differ, as comparing them would read the whole file.
+    pub fn differs_from_disk(&self) -> bool {
+        if self.is_large() {
+            return true;
+        }
+        let on_disk = match fs::read(self.path.as_str()) {
+            Ok(on_disk) => on_disk,
+            Err(_) => return true,
+        };
+        return self.format.encoding.encode(&self.format.disk_text(self.to_string())).map_or(true, |bytes| bytes != on_disk);
+    }
+
+    /// Returns how many chars cannot be represented in `encoding`, and the position of the first.
+    pub fn unmappable_chars(&self, encoding: Encoding) -> Option<(usize, (usize, usize))> {
+        let mut count = 0;
+        let mut first = None;
+        for (line_idx, line) in self.text.lines().enumerate() {
+            for (char_idx, ch) in line.chars().enumerate() {
+                if !encoding.can_encode(ch) {
+                    count += 1;
+                    first = first.or(Some((char_idx, line_idx)));
+                }
+            }
+        }
+        return first.map(|first| (count, first));
+    }
+
+    /// Applies the `.editorconfig` settings for `path` if the buffer is saved somewhere new, as they were already
+    /// applied when it was opened. Trimming whitespace is an edit to the buffer, so that it can be undone.
+    pub fn prepare_save(&mut self, path: &Path) {
+        if path != Path::new(self.path.as_str()) {
+            EditorConfig::find(path).apply_format(&mut self.format);
+        }
+
+        self.history.seal();
+        if self.format.trim_trailing_whitespace {
+            self.trim_trailing_whitespace();
+        }
+        self.history.seal();
+        self.format.final_newline = self.format.writes_final_newline();
+    }
+
+    fn trim_trailing_whitespace(&mut self) {
+        for line_idx in 0..self.text.line_count() {
+            let line = self.text.line(line_idx);
+            let start = line.trim_end().chars().count();
+            let end = line.chars().count();
+            if start < end {
+                self.remove_in_line(line_idx, start, end);
+            }
+        }
+    }
+
+    /// Removes the chars between `start` and `end` on a line as part of the current change, keeping the cursor and
+    /// anchor in place where possible.
+    fn remove_in_line(&mut self, line_idx: usize, start: usize, end: usize) {
+        let cursor_before = self.clamped_file_cursor();
+        let text = self.text.remove((start, line_idx), (end, line_idx));
+
+        self.local_cursor = self.clamped_position(cursor_before);
+        self.selection_anchor = self.selection_anchor.map(|anchor| self.clamped_position(anchor));
+        self.record(Edit::Remove { at: (start, line_idx), text }, cursor_before, self.local_cursor, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_dominant_line_ending() {
+        assert!(LineEnding::detect("no breaks").is_none());
+        assert_eq!(LineEnding::detect("a\nb\n"), Some((LineEnding::Lf, false)));
+        assert_eq!(LineEnding::detect("a\r\nb\r\n"), Some((LineEnding::Crlf, false)));
+        assert_eq!(LineEnding::detect("a\rb\r"), Some((LineEnding::Cr, false)));
+        assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), Some((LineEnding::Crlf, true)));
+        assert_eq!(LineEnding::detect("a\r\nb\n"), Some((LineEnding::Lf, true)));
+    }
+
+    #[test]
+    fn detects_encodings() {
+        assert_eq!(Encoding::detect("plain ascii".as_bytes()), Encoding::Utf8);
+        assert_eq!(Encoding::detect("caf\u{e9}".as_bytes()), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"\xEF\xBB\xBFbom"), Encoding::Utf8Bom);
+        assert_eq!(Encoding::detect(b"\xFF\xFEa\x00"), Encoding::Utf16LeBom);
+        assert_eq!(Encoding::detect(b"\xFE\xFF\x00a"), Encoding::Utf16BeBom);
+        assert_eq!(Encoding::detect(b"a\x00b\x00c\x00"), Encoding::Utf16Le);
+        assert_eq!(Encoding::detect(b"\x00a\x00b\x00c"), Encoding::Utf16Be);
+        assert_eq!(Encoding::detect(b"caf\xE9 \x80"), Encoding::Windows1252);
+    }
+
+    #[test]
+    fn round_trips_encodings() {
+        let text = "caf\u{e9} \u{20AC}\n";
+        for encoding in [Encoding::Utf8, Encoding::Utf8Bom, Encoding::Utf16Le, Encoding::Utf16LeBom, Encoding::Utf16Be, Encoding::Utf16BeBom, Encoding::Windows1252] {
+            let bytes = encoding.encode(text).unwrap();
+            assert_eq!(Encoding::detect(&bytes), encoding);
+            assert_eq!(encoding.decode(&bytes).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn reports_unmappable_chars() {
+        assert!(Encoding::Latin1.encode("\u{20AC}").is_err());
+        assert!(!Encoding::Latin1.can_encode('\u{20AC}'));
+        assert!(Encoding::Windows1252.can_encode('\u{20AC}'));
+        assert!(!Encoding::Windows1252.can_encode('\u{80}'));
+        assert!(!Encoding::Windows1252.can_encode('\u{3042}'));
+        assert_eq!(Encoding::Latin1.encode("\u{e9}").unwrap(), b"\xE9");
+    }
+
+    #[test]
+    fn round_trips_line_endings() {
+        let original = "a\r\nb\r\n\r\nc";
+        let normalized = LineEnding::normalize(original);
+        assert_eq!(normalized, "a\nb\n\nc");
+
+        let format = Format { line_ending: LineEnding::Crlf, ..Format::new() };
+        assert_eq!(format.disk_text(normalized.into_owned()), original);
+
+        let format = Format { final_newline: true, ..format };
+        assert_eq!(format.disk_text(String::from("a\nb")), "a\r\nb\r\n");
+        assert_eq!(LineEnding::normalize("a\rb\r\nc\n"), "a\nb\nc\n");
+    }
+}
@@ -0,0 +1,365 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone)]
+pub enum Edit {
+    Insert { at: (usize, usize), text: String },
+    Remove { at: (usize, usize), text: String },
+}
+
+impl Edit {
+    pub fn reversed(&self) -> Self {
+        match self {
+            Edit::Insert { at, text } => return Edit::Remove { at: *at, text: text.clone() },
+            Edit::Remove { at, text } => return Edit::Insert { at: *at, text: text.clone() },
+        }
+    }
+
+    /// Position right after `text` when it starts at `at`.
+    pub fn text_end(at: (usize, usize), text: &str) -> (usize, usize) {
+        let mut end = at;
+        for ch in text.chars() {
+            if ch == '\n' {
+                end = (0, end.1 + 1);
+            } else {
+                end.0 += 1;
+            }
+        }
+        return end;
+    }
+
+    /// Merges `other` into this edit if it directly continues it, e.g. when typing.
+    fn merge(&mut self, other: &Edit) -> bool {
+        match (self, other) {
+            (Edit::Insert { at, text }, Edit::Insert { at: other_at, text: other_text }) if Edit::text_end(*at, text) == *other_at => {
+                text.push_str(other_text);
+                return true;
+            },
+            _ => return false,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Change {
+    pub edits: Vec<Edit>,
+    pub cursor_before: (usize, usize),
+    pub cursor_after: (usize, usize),
+}
+
+struct Node {
+    change: Change,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    redo_child: Option<usize>, // Child that redo follows, i.e. the most recently visited one
+    timestamp: SystemTime,
+}
+
+/// Edit history kept as a tree, so that undoing and then editing starts a new branch instead of discarding the old one.
+/// Nodes are stored in creation order, which makes their indices double as sequence numbers.
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    coalescing: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        let root = Node {
+            change: Change { edits: Vec::new(), cursor_before: (0, 0), cursor_after: (0, 0) },
+            parent: None,
+            children: Vec::new(),
+            redo_child: None,
+            timestamp: SystemTime::now(),
+        };
+
+        return Self {
+            nodes: vec![root],
+            current: 0,
+            coalescing: false,
+        };
+    }
+
+    pub fn current(&self) -> usize {
+        return self.current;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.nodes.len();
+    }
+
+    /// Records an edit. Consecutive edits with `coalesce` set are undone as a single change.
+    pub fn record(&mut self, edit: Edit, cursor_before: (usize, usize), cursor_after: (usize, usize), coalesce: bool) {
+        if coalesce && self.coalescing && self.current != 0 {
+            let node = &mut self.nodes[self.current];
+            let merged = node.change.edits.last_mut().is_some_and(|last_edit| last_edit.merge(&edit));
+            if !merged {
+                node.change.edits.push(edit);
+            }
+            node.change.cursor_after = cursor_after;
+            node.timestamp = SystemTime::now();
+        } else {
+            let idx = self.nodes.len();
+            self.nodes.push(Node {
+                change: Change { edits: vec![edit], cursor_before, cursor_after },
+                parent: Some(self.current),
+                children: Vec::new(),
+                redo_child: None,
+                timestamp: SystemTime::now(),
+            });
+
+            let parent = &mut self.nodes[self.current];
+            parent.children.push(idx);
+            parent.redo_child = Some(idx);
+            self.current = idx;
+        }
+
+        self.coalescing = coalesce;
+    }
+
+    /// Ends the current change so that the next edit starts a new one.
+    pub fn seal(&mut self) {
+        self.coalescing = false;
+    }
+
+    pub fn undo(&mut self) -> Option<Change> {
+        self.coalescing = false;
+        let parent = self.nodes[self.current].parent?;
+        let change = self.nodes[self.current].change.clone();
+
+        self.nodes[parent].redo_child = Some(self.current);
+        self.current = parent;
+        return Some(change);
+    }
+
+    pub fn redo(&mut self) -> Option<Change> {
+        self.coalescing = false;
+        let child = self.nodes[self.current].redo_child?;
+
+        self.current = child;
+        return Some(self.nodes[child].change.clone());
+    }
+
+    /// Returns the changes to undo and then redo, in order, to move from the current node to `target`.
+    pub fn path_to(&mut self, target: usize) -> (Vec<Change>, Vec<Change>) {
+        self.coalescing = false;
+
+        let ancestors = |mut idx: usize| {
+            let mut path = vec![idx];
+            while let Some(parent) = self.nodes[idx].parent {
+                path.push(parent);
+                idx = parent;
+            }
+            return path;
+        };
+        let current_path = ancestors(self.current);
+        let target_path = ancestors(target);
+        let common = *current_path.iter().find(|idx| target_path.contains(idx)).expect("root should be a common ancestor");
+
+        let mut undo_changes = Vec::new();
+        for idx in current_path.iter().take_while(|idx| **idx != common) {
+            undo_changes.push(self.nodes[*idx].change.clone());
+        }
+
+        let mut redo_changes = Vec::new();
+        for idx in target_path.iter().take_while(|idx| **idx != common).collect::<Vec<_>>().into_iter().rev() {
+            redo_changes.push(self.nodes[*idx].change.clone());
+            let parent = self.nodes[*idx].parent.expect("non-root nodes should have a parent");
+            self.nodes[parent].redo_child = Some(*idx);
+        }
+
+        self.current = target;
+        return (undo_changes, redo_changes);
+    }
+
+    /// Returns the latest node that existed at `time`.
+    pub fn node_at(&self, time: SystemTime) -> usize {
+        return self.nodes.iter().rposition(|node| node.timestamp <= time).unwrap_or(0);
+    }
+
+    /// Returns one `(node, line)` pair per node, depth first, drawn as a tree.
+    pub fn tree_lines(&self) -> Vec<(usize, String)> {
+        let mut lines = Vec::new();
+        let mut stack = vec![(0, String::new(), String::new())]; // (node, branch prefix, prefix for children)
+
+        while let Some((idx, prefix, child_prefix)) = stack.pop() {
+            let node = &self.nodes[idx];
+            let marker = if idx == self.current { "*" } else { " " };
+            let description = if idx == 0 {
+                String::from("original")
+            } else {
+                format!("#{} ({} edits)", idx, node.change.edits.len())
+            };
+            lines.push((idx, format!("{}{}{} {}", marker, prefix, description, format_age(node.timestamp))));
+
+            // Only indent where the history actually branches
+            if let [child] = node.children[..] {
+                stack.push((child, child_prefix.clone(), child_prefix));
+                continue;
+            }
+
+            // Pushed in reverse so that the oldest branch is listed first
+            for (i, child) in node.children.iter().enumerate().rev() {
+                let last = i == node.children.len() - 1;
+                let branch = if last { "└─" } else { "├─" };
+                let continuation = if last { "  " } else { "│ " };
+                stack.push((*child, format!("{}{}", child_prefix, branch), format!("{}{}", child_prefix, continuation)));
+            }
+        }
+
+        return lines;
+    }
+}
+
+fn format_age(timestamp: SystemTime) -> String {
+    let seconds = timestamp.elapsed().map(|age| age.as_secs()).unwrap_or(0);
+    if seconds < 60 {
+        return format!("{}s ago", seconds);
+    } else if seconds < 60 * 60 {
+        return format!("{}m ago", seconds / 60);
+    } else {
+        return format!("{}h ago", seconds / (60 * 60));
+    }
+}
+
+// Serialization
+
+fn escape(text: &str) -> String {
+    return text.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r");
+}
+
+fn unescape(text: &str) -> String {
+    let mut output = String::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('n') => output.push('\n'),
+                Some('r') => output.push('\r'),
+                Some(escaped) => output.push(escaped),
+                None => (),
+            }
+        } else {
+            output.push(ch);
+        }
+    }
+    return output;
+}
+
+fn format_index(idx: Option<usize>) -> String {
+    return idx.map_or(String::from("-"), |idx| idx.to_string());
+}
+
+fn parse_index(field: &str) -> Option<Option<usize>> {
+    if field == "-" {
+        return Some(None);
+    } else {
+        return Some(Some(field.parse().ok()?));
+    }
+}
+
+fn parse_position(x: &str, y: &str) -> Option<(usize, usize)> {
+    return Some((x.parse().ok()?, y.parse().ok()?));
+}
+
+impl History {
+    /// Serializes the tree as lines of `node` records, each followed by its `edit` records.
+    pub fn serialize(&self) -> String {
+        let mut output = format!("current {}\n", self.current);
+        for node in &self.nodes {
+            let timestamp = node.timestamp.duration_since(UNIX_EPOCH).map(|age| age.as_secs()).unwrap_or(0);
+            output.push_str(&format!(
+                "node {} {} {} {} {} {} {}\n",
+                format_index(node.parent), format_index(node.redo_child), timestamp,
+                node.change.cursor_before.0, node.change.cursor_before.1,
+                node.change.cursor_after.0, node.change.cursor_after.1,
+            ));
+
+            for edit in &node.change.edits {
+                let (kind, at, text) = match edit {
+                    Edit::Insert { at, text } => ("insert", at, text),
+                    Edit::Remove { at, text } => ("remove", at, text),
+                };
+                output.push_str(&format!("edit {} {} {} {}\n", kind, at.0, at.1, escape(text)));
+            }
+        }
+        return output;
+    }
+
+    /// Returns `None` if the input is malformed.
+    pub fn deserialize(input: &str) -> Option<Self> {
+        // Only '\n' separates records, as `lines` would also strip a '\r' that ends an edit's text
+        let mut lines = input.strip_suffix('\n').unwrap_or(input).split('\n');
+        let current: usize = lines.next()?.strip_prefix("current ")?.parse().ok()?;
+
+        let mut nodes: Vec<Node> = Vec::new();
+        for line in lines {
+            let fields: Vec<&str> = line.splitn(5, ' ').collect();
+            match fields[..] {
+                ["node", parent, redo_child, timestamp, cursors] => {
+                    let cursors: Vec<&str> = cursors.split(' ').collect();
+                    if cursors.len() != 4 {
+                        return None;
+                    }
+                    let parent = parse_index(parent)?;
+                    if parent.is_some_and(|parent| parent >= nodes.len()) || parent.is_none() != nodes.is_empty() {
+                        return None;
+                    }
+
+                    let idx = nodes.len();
+                    if let Some(parent) = parent {
+                        nodes[parent].children.push(idx);
+                    }
+                    nodes.push(Node {
+                        change: Change {
+                            edits: Vec::new(),
+                            cursor_before: parse_position(cursors[0], cursors[1])?,
+                            cursor_after: parse_position(cursors[2], cursors[3])?,
+                        },
+                        parent,
+                        children: Vec::new(),
+                        redo_child: parse_index(redo_child)?,
+                        timestamp: UNIX_EPOCH + Duration::from_secs(timestamp.parse().ok()?),
+                    });
+                },
+                ["edit", kind, x, y, text] => {
+                    let at = parse_position(x, y)?;
+                    let text = unescape(text);
+                    let edit = match kind {
+                        "insert" => Edit::Insert { at, text },
+                        "remove" => Edit::Remove { at, text },
+                        _ => return None,
+                    };
+                    nodes.last_mut()?.change.edits.push(edit);
+                },
+                _ => return None,
+            }
+        }
+
+        let valid_redo_children = nodes.iter().all(|node| node.redo_child.is_none_or(|child| node.children.contains(&child)));
+        if current >= nodes.len() || !valid_redo_children {
+            return None;
+        }
+
+        return Some(Self { nodes, current, coalescing: false });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_line_breaks_and_backslashes() {
+        let text = "a\r\nb\\n\\\rc\r";
+        let mut history = History::new();
+        history.record(Edit::Insert { at: (0, 0), text: String::from(text) }, (0, 0), (1, 3), false);
+
+        let mut history = History::deserialize(&history.serialize()).unwrap();
+        assert_eq!(history.len(), 2);
+        let change = history.undo().unwrap();
+        match &change.edits[..] {
+            [Edit::Insert { at: (0, 0), text: restored }] => assert_eq!(restored, text),
+            _ => panic!("expected the insertion to be restored"),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::path::Path;
+
+use crate::app::unicode;
+
+use super::{File, editorconfig::EditorConfig, history::Edit};
+
+/// Only the start of a file is looked at to detect its indentation, which keeps opening large files fast.
+const DETECTION_LINES: usize = 1000;
+
+/// How a buffer is indented by Tab and Shift+Tab, and how wide tab characters are displayed.
+#[derive(Clone, Copy)]
+pub struct Indentation {
+    pub expand_tab: bool, // Indent with spaces instead of tab characters
+    pub width: usize, // Columns per level of indentation
+    pub tab_width: usize,
+}
+
+impl Indentation {
+    pub fn new() -> Self {
+        return Self { expand_tab: true, width: 4, tab_width: 4 };
+    }
+
+    /// Parses an override such as "tabs", "spaces 2" or just "2", keeping the tab width.
+    pub fn parse(&self, input: &str) -> Option<Self> {
+        let mut words = input.split_whitespace();
+        let (expand_tab, width) = match (words.next()?, words.next()) {
+            ("tab" | "tabs", None) => (false, self.tab_width),
+            ("space" | "spaces", None) => (true, self.width),
+            ("space" | "spaces", Some(width)) | (width, None) => (true, width.parse().ok()?),
+            _ => return None,
+        };
+
+        if words.next().is_some() || !(1..=16).contains(&width) {
+            return None;
+        }
+        return Some(Self { expand_tab, width, tab_width: self.tab_width });
+    }
+
+    /// Describes the indentation for the status bar, e.g. "tabs" or "2 spaces".
+    pub fn describe(&self) -> String {
+        if self.expand_tab {
+            return format!("{} spaces", self.width);
+        } else {
+            return String::from("tabs");
+        }
+    }
+
+    /// Returns the text that indents from display `column` to the next level.
+    fn indent_at(&self, column: usize) -> String {
+        if self.expand_tab {
+            return " ".repeat(self.width - column % self.width);
+        } else {
+            return String::from('\t');
+        }
+    }
+
+    /// Returns the number of chars a single level of indentation takes up at the start of `line`.
+    fn indent_length(&self, line: &str) -> usize {
+        if line.starts_with('\t') {
+            return 1;
+        }
+        return line.chars().take(self.width).take_while(|ch| *ch == ' ').count();
+    }
+
+    /// Works out whether `lines` are indented with tabs or spaces, and how many spaces make up a level, from
+    /// the changes in indentation between consecutive lines. Returns `None` if nothing is indented.
+    fn detect<'a>(&self, lines: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let mut tab_lines = 0;
+        let mut space_lines = 0;
+        let mut widths = [0; 9]; // How often the indentation grows by each number of spaces
+        let mut previous_spaces = 0;
+
+        for line in lines {
+            let spaces = line.chars().take_while(|ch| *ch == ' ').count();
+            if line.starts_with('\t') {
+                tab_lines += 1;
+                continue;
+            } else if line.trim().is_empty() {
+                continue; // Blank lines say nothing about indentation
+            }
+
+            if spaces > 0 {
+                space_lines += 1;
+            }
+            if spaces > previous_spaces && spaces - previous_spaces < widths.len() {
+                widths[spaces - previous_spaces] += 1;
+            }
+            previous_spaces = spaces;
+        }
+
+        if tab_lines == 0 && space_lines == 0 {
+            return None;
+        } else if tab_lines > space_lines {
+            return Some(Self { expand_tab: false, width: self.tab_width, tab_width: self.tab_width });
+        }
+
+        // Single spaces are more often alignment than indentation, so they are left out
+        let width = (2..widths.len()).max_by_key(|width| widths[*width]).filter(|width| widths[*width] > 0).unwrap_or(self.width);
+        return Some(Self { expand_tab: true, width, tab_width: self.tab_width });
+    }
+}
+
+impl File {
+    pub fn indentation(&self) -> Indentation {
+        return self.indentation;
+    }
+
+    pub fn set_indentation(&mut self, indentation: Indentation) {
+        self.indentation = indentation;
+    }
+
+    /// Uses the indentation detected from the file's contents, or `default` if they are not indented. Settings from
+    /// `.editorconfig` files take priority over both.
+    pub fn detect_indentation(&mut self, default: Indentation) {
+        let lines: Vec<_> = (0..self.text.line_count().min(DETECTION_LINES)).map(|line_idx| self.text.line(line_idx)).collect();
+        self.indentation = default.detect(lines.iter().map(|line| line.as_ref())).unwrap_or(default);
+        if !self.path.as_str().is_empty() {
+            EditorConfig::find(Path::new(self.path.as_str())).apply_indentation(&mut self.indentation);
+        }
+    }
+
+    /// Returns the lines a line-wise command acts on: the selected ones, or the cursor's line without a selection.
+    fn selected_lines(&self) -> (usize, usize) {
+        match self.selection() {
+            // A selection ending at the start of a line does not include that line
+            Some((start, end)) if end.1 > start.1 && end.0 == 0 => return (start.1, end.1 - 1),
+            Some((start, end)) => return (start.1, end.1),
+            None => return (self.local_cursor.1, self.local_cursor.1),
+        }
+    }
+
+    /// Inserts indentation up to the next tab stop, or indents every line of a selection spanning several lines.
+    pub fn insert_tab(&mut self) {
+        if self.selection().is_some_and(|(start, end)| start.1 != end.1) {
+            let (first, last) = self.selected_lines();
+            let indent = self.indentation.indent_at(0);
+            self.history.seal();
+            for line_idx in first..=last {
+                if self.text.line_length(line_idx) > 0 {
+                    self.insert_at_line_start(line_idx, &indent);
+                }
+            }
+            self.history.seal();
+            return;
+        }
+
+        let cursor = self.clamped_file_cursor();
+        let column = unicode::column(&self.text.line(cursor.1), cursor.0, self.indentation.tab_width);
+        self.insert_str(&self.indentation.indent_at(column));
+    }
+
+    /// Removes one level of indentation from the cursor's line or from every selected line.
+    pub fn dedent(&mut self) {
+        let (first, last) = self.selected_lines();
+        self.history.seal();
+        for line_idx in first..=last {
+            let length = self.indentation.indent_length(&self.text.line(line_idx));
+            if length > 0 {
+                self.remove_at_line_start(line_idx, length);
+            }
+        }
+        self.history.seal();
+    }
+
+    /// Inserts `text` at the start of a line as part of the current change, keeping the cursor and anchor in place.
+    fn insert_at_line_start(&mut self, line_idx: usize, text: &str) {
+        let cursor_before = self.clamped_file_cursor();
+        let at = (0, line_idx);
+        self.text.insert(at, text);
+
+        let length = text.chars().count();
+        let shift = |position: (usize, usize)| if position.1 == line_idx { (position.0 + length, position.1) } else { position };
+        self.local_cursor = shift(cursor_before);
+        self.selection_anchor = self.selection_anchor.map(|anchor| shift(self.clamped_position(anchor)));
+        self.record(Edit::Insert { at, text: String::from(text) }, cursor_before, self.local_cursor, true);
+    }
+
+    /// Removes `length` chars from the start of a line as part of the current change, keeping the cursor and anchor in place.
+    fn remove_at_line_start(&mut self, line_idx: usize, length: usize) {
+        let cursor_before = self.clamped_file_cursor();
+        let at = (0, line_idx);
+        let text = self.text.remove(at, (length, line_idx));
+
+        let shift = |position: (usize, usize)| if position.1 == line_idx { (position.0.saturating_sub(length), position.1) } else { position };
+        self.local_cursor = shift(cursor_before);
+        self.selection_anchor = self.selection_anchor.map(|anchor| shift(self.clamped_position(anchor)));
+        self.record(Edit::Remove { at, text }, cursor_before, self.local_cursor, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(text: &str) -> Option<Indentation> {
+        return Indentation { expand_tab: true, width: 3, tab_width: 8 }.detect(text.lines());
+    }
+
+    #[test]
+    fn parses_overrides() {
+        let indentation = Indentation { expand_tab: true, width: 3, tab_width: 8 };
+        assert!(indentation.parse("tabs").is_some_and(|parsed| !parsed.expand_tab && parsed.width == 8));
+        assert!(indentation.parse("spaces 2").is_some_and(|parsed| parsed.expand_tab && parsed.width == 2));
+        assert!(indentation.parse(" 6 ").is_some_and(|parsed| parsed.expand_tab && parsed.width == 6 && parsed.tab_width == 8));
+        assert!(indentation.parse("spaces").is_some_and(|parsed| parsed.width == 3));
+        for invalid in ["", "0", "17", "tabs 2", "spaces 2 4", "wide"] {
+            assert!(indentation.parse(invalid).is_none());
+        }
+    }
+
+    #[test]
+    fn majority_of_indented_lines_decides_between_tabs_and_spaces() {
+        assert!(detect("a {\n\tb\n\tc\n    d\n}").is_some_and(|detected| !detected.expand_tab && detected.width == 8));
+        assert!(detect("a {\n\tb\n  c\n  d\n}").is_some_and(|detected| detected.expand_tab && detected.width == 2));
+    }
+
+    #[test]
+    fn skips_single_space_alignment() {
+        let detected = detect("/*\n * comment\n * comment\n */\nfn a() {\n    b\n}").unwrap();
+        assert!(detected.expand_tab);
+        assert_eq!(detected.width, 4);
+    }
+
+    #[test]
+    fn ignores_blank_lines() {
+        // Whitespace-only lines would otherwise outvote the tabs and reset the indentation they follow
+        assert!(detect("a {\n\tb\n    \n    \n\tc\n}").is_some_and(|detected| !detected.expand_tab));
+        assert_eq!(detect("a\n    b\n\n    c\n\n    d\n      e\n        f").unwrap().width, 2);
+    }
+
+    #[test]
+    fn falls_back_to_the_default() {
+        assert!(detect("a\nb\n\nc").is_none());
+        assert_eq!(detect("a\n b\n c").unwrap().width, 3);
+    }
+}
@@ -0,0 +1,284 @@
+use std::{borrow::Cow, collections::BTreeMap, fs, io, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread};
+
+use memmap2::Mmap;
+
+/// Only every n-th line start is stored, the ones in between are found by scanning from the closest stored one.
+const LINES_PER_CHECKPOINT: usize = 64;
+const INDEX_BATCH_SIZE: usize = 1 << 20;
+
+struct LineIndex {
+    checkpoints: Vec<usize>, // Byte offsets of the starts of lines 0, LINES_PER_CHECKPOINT, 2 * LINES_PER_CHECKPOINT, ...
+    line_count: usize, // Number of lines whose start has been found so far
+}
+
+/// Edited lines replacing the original lines up to `end`, starting at the line it is stored under.
+struct Replacement {
+    end: usize,
+    lines: Vec<String>,
+}
+
+enum Location {
+    Original(usize),
+    Replaced(usize, usize), // Replacement start and the index in its lines
+}
+
+/// A memory-mapped file whose line starts are indexed in a background thread, with edits kept in an overlay.
+/// Only the lines that are asked for are ever decoded, so files much larger than memory can be viewed and edited.
+pub struct MappedText {
+    mmap: Arc<Mmap>,
+    index: Arc<Mutex<LineIndex>>,
+    indexed: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+    overlay: BTreeMap<usize, Replacement>,
+    crlf: bool, // Whether lines end in "\r\n", in which case the '\r' is left out of them and written after edited ones
+}
+
+impl MappedText {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        // SAFETY: The file is only read through the mapping, and is saved by replacing it rather than by writing into it
+        let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+        let first_break = mmap.iter().position(|byte| *byte == b'\n');
+
+        let text = Self {
+            mmap: mmap.clone(),
+            index: Arc::new(Mutex::new(LineIndex { checkpoints: vec![0], line_count: 1 })),
+            indexed: Arc::new(AtomicBool::new(false)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            overlay: BTreeMap::new(),
+            crlf: first_break.is_some_and(|position| position > 0 && mmap[position - 1] == b'\r'),
+        };
+
+        let (index, indexed, cancelled) = (text.index.clone(), text.indexed.clone(), text.cancelled.clone());
+        thread::spawn(move || {
+            let mut checkpoints = Vec::new();
+            let mut line_count = 1;
+            let mut position = 0;
+
+            while position < mmap.len() && !cancelled.load(Ordering::Relaxed) {
+                let batch_end = (position + INDEX_BATCH_SIZE).min(mmap.len());
+                for (offset, byte) in mmap[position..batch_end].iter().enumerate() {
+                    if *byte == b'\n' {
+                        if line_count % LINES_PER_CHECKPOINT == 0 {
+                            checkpoints.push(position + offset + 1);
+                        }
+                        line_count += 1;
+                    }
+                }
+                position = batch_end;
+
+                let mut index = index.lock().expect("indexing thread should not panic");
+                index.checkpoints.append(&mut checkpoints);
+                index.line_count = line_count;
+            }
+            indexed.store(true, Ordering::Relaxed);
+        });
+
+        return Ok(text);
+    }
+
+    pub fn is_indexing(&self) -> bool {
+        return !self.indexed.load(Ordering::Relaxed);
+    }
+
+    fn original_line_count(&self) -> usize {
+        return self.index.lock().expect("indexing thread should not panic").line_count;
+    }
+
+    /// Returns the number of lines indexed so far, including edits.
+    pub fn line_count(&self) -> usize {
+        let mut line_count = self.original_line_count();
+        for (start, replacement) in &self.overlay {
+            line_count = line_count + replacement.lines.len() - (replacement.end - start);
+        }
+        return line_count;
+    }
+
+    pub fn is_crlf(&self) -> bool {
+        return self.crlf;
+    }
+
+    /// Returns a line without its line break, which in a CRLF file includes the '\r'.
+    pub fn line(&self, line_idx: usize) -> Cow<'_, str> {
+        match self.locate(line_idx) {
+            Location::Original(line_idx) => {
+                let start = self.line_start(line_idx);
+                let mut end = self.line_end(start);
+                if self.crlf && end > start && end < self.mmap.len() && self.mmap[end - 1] == b'\r' {
+                    end -= 1;
+                }
+                return String::from_utf8_lossy(&self.mmap[start..end]);
+            },
+            Location::Replaced(start, idx) => return Cow::from(self.overlay[&start].lines[idx].as_str()),
+        }
+    }
+
+    /// Replaces the lines from `first` up to and including `last` with `lines`.
+    pub fn replace_lines(&mut self, first: usize, last: usize, lines: Vec<String>) {
+        let (start, mut new_lines) = match self.locate(first) {
+            Location::Original(line_idx) => (line_idx, Vec::new()),
+            Location::Replaced(start, idx) => (start, self.overlay[&start].lines[..idx].to_vec()),
+        };
+        let (end, suffix) = match self.locate(last) {
+            Location::Original(line_idx) => (line_idx + 1, Vec::new()),
+            Location::Replaced(start, idx) => (self.overlay[&start].end, self.overlay[&start].lines[idx + 1..].to_vec()),
+        };
+        new_lines.extend(lines);
+        new_lines.extend(suffix);
+
+        let merged: Vec<usize> = self.overlay.range(start..end).map(|(start, _)| *start).collect();
+        for start in merged {
+            self.overlay.remove(&start);
+        }
+        self.overlay.insert(start, Replacement { end, lines: new_lines });
+    }
+
+    /// Writes the original bytes of unedited lines as they are, and the edited lines in between with the file's line
+    /// ending.
+    pub fn write_to(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let line_break: &[u8] = if self.crlf { b"\r\n" } else { b"\n" };
+        let mut written = 0;
+        for (start, replacement) in &self.overlay {
+            writer.write_all(&self.mmap[written..self.line_start(*start)])?;
+            for (idx, line) in replacement.lines.iter().enumerate() {
+                if idx > 0 {
+                    writer.write_all(line_break)?;
+                }
+                writer.write_all(line.as_bytes())?;
+            }
+
+            // The original line break after the replaced lines separates them from what follows
+            written = self.line_end(self.line_start(replacement.end - 1));
+            if written < self.mmap.len() {
+                writer.write_all(line_break)?;
+                written += 1;
+            }
+        }
+        return writer.write_all(&self.mmap[written..]);
+    }
+
+    fn locate(&self, line_idx: usize) -> Location {
+        let mut shift = 0; // Lines added before the current replacement minus lines removed
+        for (start, replacement) in &self.overlay {
+            let shifted_start = (*start as isize + shift) as usize;
+            if line_idx < shifted_start {
+                break;
+            } else if line_idx < shifted_start + replacement.lines.len() {
+                return Location::Replaced(*start, line_idx - shifted_start);
+            }
+            shift += replacement.lines.len() as isize - (replacement.end - start) as isize;
+        }
+        return Location::Original((line_idx as isize - shift) as usize);
+    }
+
+    fn line_start(&self, line_idx: usize) -> usize {
+        let mut position = self.index.lock().expect("indexing thread should not panic").checkpoints[line_idx / LINES_PER_CHECKPOINT];
+        for _ in 0..line_idx % LINES_PER_CHECKPOINT {
+            position = self.line_end(position) + 1;
+        }
+        return position;
+    }
+
+    /// Returns the position of the line break ending the line that contains `position`, or the end of the file.
+    fn line_end(&self, position: usize) -> usize {
+        return self.mmap[position..].iter().position(|byte| *byte == b'\n').map_or(self.mmap.len(), |offset| position + offset);
+    }
+}
+
+impl Drop for MappedText {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    /// Maps a file with `content`, waiting until its lines are indexed.
+    fn open(name: &str, content: &str) -> MappedText {
+        let path = env::temp_dir().join(format!("mate-mapped-test-{}-{}", name, process::id()));
+        fs::write(&path, content).unwrap();
+        let text = MappedText::open(path.to_str().unwrap()).unwrap();
+        while text.is_indexing() {
+            thread::yield_now();
+        }
+        // The mapping stays valid after the file is removed
+        fs::remove_file(&path).unwrap();
+        return text;
+    }
+
+    fn written(text: &MappedText) -> String {
+        let mut output = Vec::new();
+        text.write_to(&mut output).unwrap();
+        return String::from_utf8(output).unwrap();
+    }
+
+    fn numbered_lines(count: usize) -> String {
+        return (0..count).map(|line_idx| format!("line {}\n", line_idx)).collect();
+    }
+
+    #[test]
+    fn finds_lines_between_checkpoints() {
+        let text = open("checkpoints", &numbered_lines(200));
+        assert_eq!(text.line_count(), 201);
+        for line_idx in [0, 63, 64, 130, 199] {
+            assert_eq!(text.line(line_idx), format!("line {}", line_idx));
+        }
+        assert_eq!(text.line(200), "");
+    }
+
+    #[test]
+    fn shifts_lines_after_edits() {
+        let mut text = open("shift", &numbered_lines(100));
+
+        // Inserted lines push the following ones down
+        text.replace_lines(10, 10, vec![String::from("a"), String::from("b"), String::from("c")]);
+        assert_eq!(text.line_count(), 103);
+        assert_eq!(text.line(12), "c");
+        assert_eq!(text.line(13), "line 11");
+
+        // Removed lines pull them up again, past the earlier replacement
+        text.replace_lines(50, 52, vec![String::from("x")]);
+        assert_eq!(text.line_count(), 101);
+        assert_eq!(text.line(49), "line 47");
+        assert_eq!(text.line(50), "x");
+        assert_eq!(text.line(51), "line 51");
+
+        // Replacing lines that overlap an earlier replacement merges them
+        text.replace_lines(11, 13, vec![String::from("merged")]);
+        assert_eq!(text.line_count(), 99);
+        assert_eq!(text.line(10), "a");
+        assert_eq!(text.line(11), "merged");
+        assert_eq!(text.line(12), "line 12");
+        assert_eq!(text.line(48), "x");
+    }
+
+    #[test]
+    fn writes_edits_with_and_without_final_newline() {
+        let mut text = open("final-newline", "a\nb\nc\n");
+        text.replace_lines(1, 1, vec![String::from("b1"), String::from("b2")]);
+        assert_eq!(written(&text), "a\nb1\nb2\nc\n");
+
+        let mut text = open("no-final-newline", "a\nb\nc");
+        text.replace_lines(1, 1, vec![String::from("B")]);
+        assert_eq!(written(&text), "a\nB\nc");
+    }
+
+    #[test]
+    fn edits_the_last_line() {
+        let mut text = open("last-line", "a\nb");
+        text.replace_lines(1, 1, vec![String::from("b!"), String::from("new")]);
+        assert_eq!(written(&text), "a\nb!\nnew");
+        text.replace_lines(0, 2, vec![String::from("joined")]);
+        assert_eq!(text.line_count(), 1);
+        assert_eq!(written(&text), "joined");
+
+        // After a final newline, the last line is the empty one following it
+        let mut text = open("after-final-newline", "a\n");
+        text.replace_lines(1, 1, vec![String::from("b")]);
+        assert_eq!(written(&text), "a\nb");
+    }
+}
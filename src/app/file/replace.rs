@@ -0,0 +1,176 @@
+use super::{File, history::Edit, search::{Match, SearchOptions}};
+
+/// An interactive search and replace. Goes through the matches from where it started, wrapping around once.
+pub struct ReplaceSession {
+    replacement: String,
+    origin: (usize, usize),
+    wrapped: bool,
+    pub replaced: usize,
+}
+
+/// Whether position `a` comes before `b`. Positions are (char, line), so they are swapped to compare line first.
+fn is_before(a: (usize, usize), b: (usize, usize)) -> bool {
+    return (a.1, a.0) < (b.1, b.0);
+}
+
+impl File {
+    /// Starts replacing matches of `pattern` with `replacement`, in which `$1` and `${name}` refer to capture groups
+    /// in regex mode. Otherwise the replacement is inserted literally.
+    /// Moves to the first match at or after the cursor, and returns `None` if there is nothing to replace.
+    pub fn start_replace(&mut self, pattern: &str, options: SearchOptions, replacement: &str) -> Option<ReplaceSession> {
+        // All replacements are recorded as a single change, starting here
+        self.history.seal();
+        self.selection_anchor = None;
+
+        let origin = self.clamped_file_cursor();
+        self.search = Some(self.new_search(pattern, options, origin.1));
+        let mut session = ReplaceSession { replacement: String::from(replacement), origin, wrapped: false, replaced: 0 };
+
+        if self.next_replace_match(&mut session, origin, true) {
+            return Some(session);
+        } else {
+            self.finish_replace();
+            return None;
+        }
+    }
+
+    /// Moves to the next match of the session after `from`. Returns false once all matches have been visited.
+    fn next_replace_match(&mut self, session: &mut ReplaceSession, from: (usize, usize), inclusive: bool) -> bool {
+        self.refresh_search();
+        let matches = match &self.search {
+            Some(search) => &search.matches,
+            None => return false,
+        };
+
+        let is_after = |m: &&Match| if inclusive { !is_before((m.start, m.line), from) } else { is_before(from, (m.start, m.line)) };
+        let mut found = matches.iter().find(is_after).copied();
+        if found.is_none() && !session.wrapped {
+            session.wrapped = true;
+            found = matches.first().copied();
+        }
+
+        match found.filter(|m| !session.wrapped || is_before((m.start, m.line), session.origin)) {
+            Some(m) => {
+                self.local_cursor = (m.start, m.line);
+                return true;
+            },
+            None => return false,
+        }
+    }
+
+    /// Replaces a single match, expanding capture group references, and returns the position after the replacement.
+    fn replace_match(&mut self, session: &mut ReplaceSession, m: Match) -> (usize, usize) {
+        let at = (m.start, m.line);
+        let regex = match self.search.as_ref().and_then(|search| search.regex.clone()) {
+            Some(regex) => regex,
+            None => return at,
+        };
+
+        let line = self.text.line(m.line);
+        let start_byte = self.text.byte_idx(at);
+        let captures = match regex.captures_at(&line, start_byte).filter(|captures| captures.get(0).is_some_and(|found| found.start() == start_byte)) {
+            Some(captures) => captures,
+            None => return at,
+        };
+        let mut replacement = String::new();
+        if self.search.as_ref().is_some_and(|search| search.options.regex) {
+            captures.expand(&session.replacement, &mut replacement);
+        } else {
+            replacement.push_str(&session.replacement);
+        }
+
+        let cursor_before = self.clamped_file_cursor();
+        let removed = self.text.remove(at, (m.end, m.line));
+        self.record(Edit::Remove { at, text: removed }, cursor_before, at, true);
+        let end = self.text.insert(at, &replacement);
+        if !replacement.is_empty() {
+            self.record(Edit::Insert { at, text: replacement }, at, end, true);
+        }
+
+        // Keep the origin on the same text when something before it on its line changes length
+        if m.line == session.origin.1 && m.end <= session.origin.0 && end.1 == m.line {
+            session.origin.0 = session.origin.0 - m.end + end.0;
+        }
+
+        session.replaced += 1;
+        self.local_cursor = end;
+        return end;
+    }
+
+    /// Replaces the match at the cursor and moves to the next one. Returns false once all matches have been visited.
+    pub fn replace_current(&mut self, session: &mut ReplaceSession) -> bool {
+        self.refresh_search();
+        let cursor = self.clamped_file_cursor();
+        let current = self.search.as_ref().and_then(|search| search.match_at(cursor).map(|idx| search.matches[idx]));
+
+        let from = match current {
+            Some(m) => self.replace_match(session, m),
+            None => cursor,
+        };
+        return self.next_replace_match(session, from, true);
+    }
+
+    /// Moves to the next match without replacing the current one. Returns false once all matches have been visited.
+    pub fn skip_current(&mut self, session: &mut ReplaceSession) -> bool {
+        let cursor = self.clamped_file_cursor();
+        return self.next_replace_match(session, cursor, false);
+    }
+
+    /// Replaces all matches that have not been visited yet.
+    pub fn replace_all(&mut self, session: &mut ReplaceSession) {
+        self.refresh_search();
+        let cursor = self.clamped_file_cursor();
+        let remaining: Vec<Match> = match &self.search {
+            Some(search) => search.matches.iter()
+                .filter(|m| {
+                    let position = (m.start, m.line);
+                    let after_cursor = !is_before(position, cursor);
+                    let before_origin = is_before(position, session.origin);
+                    if session.wrapped { after_cursor && before_origin } else { after_cursor || before_origin }
+                })
+                .copied()
+                .collect(),
+            None => return,
+        };
+
+        // Replaced back to front so that the positions of the remaining matches stay valid
+        for m in remaining.into_iter().rev() {
+            self.replace_match(session, m);
+        }
+        self.local_cursor = cursor;
+    }
+
+    pub fn finish_replace(&mut self) {
+        self.history.seal();
+        self.search = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replace_all(text: &str, pattern: &str, regex: bool, replacement: &str) -> String {
+        let mut file = File::new();
+        file.insert_str(text);
+        file.set_cursor(0, 0);
+
+        let options = SearchOptions { regex, ..SearchOptions::new() };
+        let mut session = file.start_replace(pattern, options, replacement).unwrap();
+        file.replace_all(&mut session);
+        file.finish_replace();
+        return file.to_string();
+    }
+
+    #[test]
+    fn inserts_literal_replacements_without_regex() {
+        assert_eq!(replace_all("price: 5", "5", false, "$5"), "price: $5");
+        assert_eq!(replace_all("a (b) a", "a", false, "${price}$$"), "${price}$$ (b) ${price}$$");
+    }
+
+    #[test]
+    fn expands_capture_groups_in_regex_mode() {
+        assert_eq!(replace_all("key=value", r"(\w+)=(\w+)", true, "$2=$1"), "value=key");
+        assert_eq!(replace_all("cost 5", r"(\d)", true, "$$$1"), "cost $5");
+    }
+}
@@ -0,0 +1,142 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use super::text::Text;
+
+#[derive(Clone, Copy)]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+}
+
+impl SearchOptions {
+    pub fn new() -> Self {
+        return Self { regex: false, case_sensitive: true, whole_word: false };
+    }
+
+    /// Lists the enabled options, e.g. "regex, whole word".
+    pub fn describe(&self) -> String {
+        let mut enabled = Vec::new();
+        if self.regex {
+            enabled.push("regex");
+        }
+        if !self.case_sensitive {
+            enabled.push("ignore case");
+        }
+        if self.whole_word {
+            enabled.push("whole word");
+        }
+        return enabled.join(", ");
+    }
+
+    /// Builds the regex searched for. Plain text patterns are escaped so that they match literally.
+    pub fn build_regex(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        let mut pattern = if self.regex { String::from(pattern) } else { regex::escape(pattern) };
+        if self.whole_word {
+            pattern = format!(r"\b(?:{})\b", pattern);
+        }
+        return RegexBuilder::new(&pattern).case_insensitive(!self.case_sensitive).build();
+    }
+}
+
+/// A match on a single line, as char indices.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Match {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub struct Search {
+    pub pattern: String,
+    pub options: SearchOptions,
+    pub regex: Option<Regex>, // None if the pattern is empty or invalid
+    pub matches: Vec<Match>,
+    pub revision: u64, // File revision the matches were found in
+    pub window: Option<Range<usize>>, // Lines that were searched, if not the whole text
+}
+
+impl Search {
+    /// Searches the lines in `window`, or the whole text if it is `None`.
+    pub fn new(pattern: &str, options: SearchOptions, text: &Text, revision: u64, window: Option<Range<usize>>) -> Self {
+        let regex = if pattern.is_empty() { None } else { options.build_regex(pattern).ok() };
+        let lines = window.clone().unwrap_or(0..text.line_count());
+        let matches = regex.as_ref().map_or(Vec::new(), |regex| find_matches(text, regex, lines));
+        return Self {
+            pattern: String::from(pattern),
+            options,
+            regex,
+            matches,
+            revision,
+            window,
+        };
+    }
+
+    pub fn is_valid(&self) -> bool {
+        return self.pattern.is_empty() || self.regex.is_some();
+    }
+
+    /// Returns the index of the first match at or after (or strictly after) `position`, wrapping around unless only a
+    /// window was searched.
+    pub fn next_from(&self, position: (usize, usize), inclusive: bool) -> Option<usize> {
+        let after = |m: &Match| {
+            let start = (m.line, m.start);
+            let position = (position.1, position.0);
+            if inclusive { start >= position } else { start > position }
+        };
+        if self.matches.is_empty() {
+            return None;
+        }
+        let found = self.matches.iter().position(after);
+        return if self.window.is_some() { found } else { Some(found.unwrap_or(0)) };
+    }
+
+    /// Returns the index of the last match strictly before `position`, wrapping around unless only a window was
+    /// searched.
+    pub fn previous_from(&self, position: (usize, usize)) -> Option<usize> {
+        if self.matches.is_empty() {
+            return None;
+        }
+        let before = self.matches.partition_point(|m| (m.line, m.start) < (position.1, position.0));
+        if before == 0 {
+            return if self.window.is_some() { None } else { Some(self.matches.len() - 1) };
+        }
+        return Some(before - 1);
+    }
+
+    /// Returns the index of the match starting at `position`, if any.
+    pub fn match_at(&self, position: (usize, usize)) -> Option<usize> {
+        return self.matches.iter().position(|m| (m.start, m.line) == position);
+    }
+
+    /// Returns the matches on the given line.
+    pub fn on_line(&self, line_idx: usize) -> &[Match] {
+        let start = self.matches.partition_point(|m| m.line < line_idx);
+        let end = self.matches.partition_point(|m| m.line <= line_idx);
+        return &self.matches[start..end];
+    }
+}
+
+/// Finds all non-empty matches, converting byte offsets to char indices so that they can be used as cursor positions.
+pub fn find_matches(text: &Text, regex: &Regex, lines: Range<usize>) -> Vec<Match> {
+    let mut matches = Vec::new();
+    for line_idx in lines {
+        let line = text.line(line_idx);
+        // Matches are in order, so chars only need to be counted from the previous match
+        let (mut byte_idx, mut char_idx) = (0, 0);
+        let mut to_char_idx = |byte: usize| {
+            char_idx += line[byte_idx..byte].chars().count();
+            byte_idx = byte;
+            return char_idx;
+        };
+
+        for found in regex.find_iter(&line).filter(|found| !found.is_empty()) {
+            let start = to_char_idx(found.start());
+            let end = to_char_idx(found.end());
+            matches.push(Match { line: line_idx, start, end });
+        }
+    }
+    return matches;
+}
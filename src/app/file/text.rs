@@ -0,0 +1,277 @@
+use std::{borrow::Cow, fmt, io};
+
+use ropey::{Rope, RopeSlice};
+
+use super::mapped::MappedText;
+
+/// The contents of a file. Positions are (char, line), where lines are separated by '\n' only and the char index
+/// excludes the line break.
+pub enum Text {
+    /// Stored in a rope, so that indexing, inserting and removing are O(log n) in its size.
+    Rope(Rope),
+    /// Memory-mapped, for files too large to keep in memory.
+    Mapped(MappedText),
+}
+
+impl Text {
+    pub fn new() -> Self {
+        return Text::Rope(Rope::new());
+    }
+
+    pub fn from(src: &str) -> Self {
+        return Text::Rope(Rope::from_str(src));
+    }
+
+    pub fn open_mapped(path: &str) -> io::Result<Self> {
+        return Ok(Text::Mapped(MappedText::open(path)?));
+    }
+
+    pub fn line_count(&self) -> usize {
+        match self {
+            Text::Rope(rope) => return rope.len_lines(),
+            Text::Mapped(mapped) => return mapped.line_count(),
+        }
+    }
+
+    /// Returns the number of chars on a line, without its line break.
+    pub fn line_length(&self, line_idx: usize) -> usize {
+        match self {
+            Text::Rope(rope) => return line_slice(rope, line_idx).len_chars(),
+            Text::Mapped(mapped) => return mapped.line(line_idx).chars().count(),
+        }
+    }
+
+    /// Returns a line without its line break. Only allocates if the line is split across chunks of the rope.
+    pub fn line(&self, line_idx: usize) -> Cow<'_, str> {
+        match self {
+            Text::Rope(rope) => return Cow::from(line_slice(rope, line_idx)),
+            Text::Mapped(mapped) => return mapped.line(line_idx),
+        }
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        return (0..self.line_count()).map(|line_idx| self.line(line_idx));
+    }
+
+    /// Returns the text between `start` and `end`, which may span several lines.
+    pub fn slice(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        match self {
+            Text::Rope(rope) => return String::from(rope.slice(char_idx(rope, start)..char_idx(rope, end))),
+            Text::Mapped(_) => {
+                let first_line = self.line(start.1);
+                if start.1 == end.1 {
+                    return String::from(&first_line[byte_idx(&first_line, start.0)..byte_idx(&first_line, end.0)]);
+                }
+
+                let mut text = String::from(&first_line[byte_idx(&first_line, start.0)..]);
+                for line_idx in start.1 + 1..end.1 {
+                    text.push('\n');
+                    text.push_str(&self.line(line_idx));
+                }
+                let last_line = self.line(end.1);
+                text.push('\n');
+                text.push_str(&last_line[..byte_idx(&last_line, end.0)]);
+                return text;
+            },
+        }
+    }
+
+    /// Converts a position into the byte index on its line.
+    pub fn byte_idx(&self, position: (usize, usize)) -> usize {
+        match self {
+            Text::Rope(rope) => return line_slice(rope, position.1).char_to_byte(position.0),
+            Text::Mapped(mapped) => return byte_idx(&mapped.line(position.1), position.0),
+        }
+    }
+
+    /// Inserts `text`, which may span several lines, at `at` and returns the position right after it.
+    pub fn insert(&mut self, at: (usize, usize), text: &str) -> (usize, usize) {
+        match self {
+            Text::Rope(rope) => {
+                let char_idx = char_idx(rope, at);
+                rope.insert(char_idx, text);
+
+                let end_char_idx = char_idx + text.chars().count();
+                let end_line_idx = rope.char_to_line(end_char_idx);
+                return (end_char_idx - rope.line_to_char(end_line_idx), end_line_idx);
+            },
+            Text::Mapped(mapped) => {
+                let line = mapped.line(at.1).into_owned();
+                let (prefix, suffix) = line.split_at(byte_idx(&line, at.0));
+                let lines: Vec<String> = format!("{}{}{}", prefix, text, suffix).split('\n').map(String::from).collect();
+
+                let end = (lines[lines.len() - 1].chars().count() - suffix.chars().count(), at.1 + lines.len() - 1);
+                mapped.replace_lines(at.1, at.1, lines);
+                return end;
+            },
+        }
+    }
+
+    /// Removes the text between `start` and `end`, which may span several lines, and returns it.
+    pub fn remove(&mut self, start: (usize, usize), end: (usize, usize)) -> String {
+        let removed = self.slice(start, end);
+        match self {
+            Text::Rope(rope) => rope.remove(char_idx(rope, start)..char_idx(rope, end)),
+            Text::Mapped(mapped) => {
+                let first_line = mapped.line(start.1);
+                let last_line = mapped.line(end.1);
+                let joined = format!("{}{}", &first_line[..byte_idx(&first_line, start.0)], &last_line[byte_idx(&last_line, end.0)..]);
+                mapped.replace_lines(start.1, end.1, vec![joined]);
+            },
+        }
+        return removed;
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        return matches!(self, Text::Mapped(_));
+    }
+
+    /// Whether the lines of a memory-mapped file end in "\r\n". Other text only ever contains '\n'.
+    pub fn is_crlf(&self) -> bool {
+        return matches!(self, Text::Mapped(mapped) if mapped.is_crlf());
+    }
+
+    /// Whether line starts are still being indexed, so that more lines may appear.
+    pub fn is_indexing(&self) -> bool {
+        return matches!(self, Text::Mapped(mapped) if mapped.is_indexing());
+    }
+
+    pub fn write_to(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        match self {
+            Text::Rope(rope) => {
+                for chunk in rope.chunks() {
+                    writer.write_all(chunk.as_bytes())?;
+                }
+                return Ok(());
+            },
+            Text::Mapped(mapped) => return mapped.write_to(writer),
+        }
+    }
+}
+
+fn line_slice(rope: &Rope, line_idx: usize) -> RopeSlice<'_> {
+    let line = rope.line(line_idx);
+    let length = line.len_chars();
+    if length > 0 && line.char(length - 1) == '\n' {
+        return line.slice(..length - 1);
+    } else {
+        return line;
+    }
+}
+
+fn char_idx(rope: &Rope, position: (usize, usize)) -> usize {
+    return rope.line_to_char(position.1) + position.0;
+}
+
+fn byte_idx(line: &str, char_idx: usize) -> usize {
+    return line.char_indices().nth(char_idx).map_or(line.len(), |(byte_idx, _)| byte_idx);
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Text::Rope(rope) => {
+                for chunk in rope.chunks() {
+                    f.write_str(chunk)?;
+                }
+            },
+            Text::Mapped(_) => {
+                for (line_idx, line) in self.lines().enumerate() {
+                    if line_idx > 0 {
+                        f.write_str("\n")?;
+                    }
+                    f.write_str(&line)?;
+                }
+            },
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    /// Maps a file with `content`, waiting until its lines are indexed.
+    fn mapped(name: &str, content: &str) -> Text {
+        let path = env::temp_dir().join(format!("mate-text-test-{}-{}", name, process::id()));
+        fs::write(&path, content).unwrap();
+        let text = Text::open_mapped(path.to_str().unwrap()).unwrap();
+        while text.is_indexing() {
+            std::thread::yield_now();
+        }
+        // The mapping stays valid after the file is removed
+        fs::remove_file(&path).unwrap();
+        return text;
+    }
+
+    fn written(text: &Text) -> String {
+        let mut output = Vec::new();
+        text.write_to(&mut output).unwrap();
+        return String::from_utf8(output).unwrap();
+    }
+
+    #[test]
+    fn inserts_and_removes_across_lines() {
+        let mut text = Text::from("ab\ncd");
+        assert_eq!(text.insert((1, 0), "x\ny"), (1, 1));
+        assert_eq!(text.to_string(), "ax\nyb\ncd");
+        assert_eq!(text.line_count(), 3);
+
+        assert_eq!(text.remove((1, 0), (1, 1)), "x\ny");
+        assert_eq!(text.to_string(), "ab\ncd");
+        assert_eq!(text.remove((2, 0), (0, 1)), "\n");
+        assert_eq!(text.to_string(), "abcd");
+        assert_eq!(text.line_count(), 1);
+    }
+
+    #[test]
+    fn lines_exclude_their_line_break() {
+        let text = Text::from("a\nbc\n");
+        assert_eq!(text.line_count(), 3);
+        assert_eq!(text.line(1), "bc");
+        assert_eq!(text.line_length(1), 2);
+        assert_eq!(text.line(2), "");
+
+        let text = Text::from("a\nbc");
+        assert_eq!(text.line_count(), 2);
+        assert_eq!(text.line(1), "bc");
+        assert_eq!(text.slice((0, 0), (2, 1)), "a\nbc");
+    }
+
+    #[test]
+    fn converts_positions_to_indices() {
+        let mut text = Text::from("\u{e4}b\nc\u{f6}d");
+        let rope = match &text {
+            Text::Rope(rope) => rope,
+            Text::Mapped(_) => panic!("text should be stored in a rope"),
+        };
+        assert_eq!(char_idx(rope, (0, 0)), 0);
+        assert_eq!(char_idx(rope, (1, 1)), 4);
+        assert_eq!(rope.char_to_line(4), 1);
+        assert_eq!(text.byte_idx((1, 0)), 2);
+        assert_eq!(text.byte_idx((2, 1)), 3);
+        assert_eq!(text.insert((2, 1), "\u{e9}\n"), (0, 2));
+        assert_eq!(text.line(1), "c\u{f6}\u{e9}");
+    }
+
+    #[test]
+    fn hides_carriage_returns_of_mapped_crlf_lines() {
+        // Backspace at the start of a line joins it with the previous one
+        let mut text = mapped("crlf-join", "123\r\nabc\r\nxyz\r\n");
+        assert!(text.is_crlf());
+        assert_eq!(text.line(0), "123");
+        assert_eq!(text.line_length(0), 3);
+        assert_eq!(text.slice((1, 0), (1, 1)), "23\na");
+        assert_eq!(text.remove((3, 0), (0, 1)), "\n");
+        assert_eq!(written(&text), "123abc\r\nxyz\r\n");
+
+        // Typing at the end of a line, and breaking it
+        let mut text = mapped("crlf-type", "123\r\nabc\r\n");
+        let end = text.insert((text.line_length(0), 0), "X");
+        assert_eq!(text.insert(end, "\n"), (0, 1));
+        assert_eq!(written(&text), "123X\r\n\r\nabc\r\n");
+    }
+}
@@ -0,0 +1,66 @@
+use std::{env, fs, io, path::{Path, PathBuf}};
+
+use super::history::History;
+
+const HEADER: &str = "mate-undo 1";
+
+/// FNV-1a, which unlike `DefaultHasher` is guaranteed to stay the same between builds.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+fn store_dir() -> Option<PathBuf> {
+    if let Some(data_home) = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(data_home).join("mate").join("undo"));
+    }
+    let home = env::var_os("HOME").filter(|dir| !dir.is_empty())?;
+    return Some(PathBuf::from(home).join(".local").join("share").join("mate").join("undo"));
+}
+
+/// Returns the canonical path of `path` and where its history is stored.
+fn store_path(path: &Path) -> io::Result<(PathBuf, PathBuf)> {
+    let canonical_path = fs::canonicalize(path)?;
+    let dir = store_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no directory to store undo history in"))?;
+    let store_path = dir.join(format!("{:016x}", hash(canonical_path.to_string_lossy().as_bytes())));
+    return Ok((canonical_path, store_path));
+}
+
+pub fn save(path: &Path, content: &str, history: &History) -> io::Result<()> {
+    let (canonical_path, store_path) = store_path(path)?;
+    if let Some(dir) = store_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let output = format!(
+        "{}\npath {}\nhash {:016x}\n{}",
+        HEADER, canonical_path.to_string_lossy(), hash(content.as_bytes()), history.serialize(),
+    );
+
+    // Written next to the store first so that a crash never leaves a half written history behind
+    let temp_path = store_path.with_extension("tmp");
+    fs::write(&temp_path, output)?;
+    return fs::rename(&temp_path, &store_path);
+}
+
+/// Returns the stored history of `path` if it was saved with exactly `content`. Stale histories are removed.
+pub fn load(path: &Path, content: &str) -> Option<History> {
+    let (canonical_path, store_path) = store_path(path).ok()?;
+    let stored = fs::read_to_string(&store_path).ok()?;
+
+    let mut lines = stored.splitn(4, '\n');
+    let valid_header = lines.next() == Some(HEADER)
+        && lines.next() == Some(format!("path {}", canonical_path.to_string_lossy()).as_str())
+        && lines.next() == Some(format!("hash {:016x}", hash(content.as_bytes())).as_str());
+
+    let history = lines.next().filter(|_| valid_header).and_then(History::deserialize);
+    if history.is_none() {
+        // The file has changed since (or the store is corrupt), so the edits would no longer line up with it
+        let _ = fs::remove_file(&store_path);
+    }
+    return history;
+}
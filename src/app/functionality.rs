@@ -1,78 +1,689 @@
+use std::{fs, path::PathBuf, time::Duration};
+
 use crossterm::event::{Event, KeyEvent, KeyModifiers, KeyCode};
+use tui::layout::Direction;
 
 use self::save::save;
 
-use super::{Data, State, unicode::UnicodeString};
+use super::{Data, State, file::{Encoding, File, LineEnding}, grep::Grep, project_replace::ProjectReplace, prompt::Prompt, unicode::UnicodeString};
+
+pub mod save;
 
-mod save;
+const RESIZE_STEP: i16 = 5;
 
 // TODO: add handle_key_event function to File and Prompt
 
+fn edit_prompt(key: KeyEvent, prompt: &mut Prompt) {
+    if key.code == KeyCode::Left {
+        prompt.move_cursor_left();
+    } else if key.code == KeyCode::Right {
+        prompt.move_cursor_right();
+    }
+
+    else if key.code == KeyCode::Backspace {
+        prompt.remove_character_before();
+    } else if key.code == KeyCode::Delete {
+        prompt.remove_character_after();
+    } else if let KeyCode::Char(ch) = key.code {
+        prompt.write_character(ch);
+    }
+}
+
 fn handle_edit_key(key: KeyEvent, data: &mut Data) {
     if key.modifiers == KeyModifiers::CONTROL {
         if key.code == KeyCode::Char('c') {
             data.state = State::Quitting;
+        } else if key.code == KeyCode::Char('f') {
+            data.state = State::Searching;
+            let file = data.buffers.active();
+            data.search_origin = (file.view(), file.cursor());
+            data.search_prompt.set_answer(&UnicodeString::new());
+        } else if key.code == KeyCode::Char('e') {
+            data.state = State::Replacing;
+            let file = data.buffers.active();
+            data.search_origin = (file.view(), file.cursor());
+            search_incrementally(data, State::Replacing);
+        } else if key.code == KeyCode::Char('g') {
+            data.state = State::Grepping;
+        } else if key.code == KeyCode::Char('x') {
+            if let Some(text) = data.buffers.active_mut().cut_selection() {
+                data.registers.store(text);
+            }
+        } else if key.code == KeyCode::Char('v') {
+            let text = String::from(data.registers.load());
+            data.buffers.active_mut().insert_str(&text);
+        } else if key.code == KeyCode::Char('r') {
+            data.state = State::SelectingRegister;
+        } else if key.code == KeyCode::Char('z') {
+            data.buffers.active_mut().undo();
+        } else if key.code == KeyCode::Char('y') {
+            data.buffers.active_mut().redo();
+        } else if key.code == KeyCode::Char('t') {
+            data.state = State::TimeTraveling;
+            data.time_prompt.set_answer(&UnicodeString::new());
+        } else if key.code == KeyCode::Char('u') {
+            data.state = State::BrowsingHistory;
+            let file = data.buffers.active();
+            let (nodes, lines): (Vec<usize>, Vec<String>) = file.history_tree().into_iter().unzip();
+            let selected_idx = nodes.iter().position(|node| *node == file.history_node()).unwrap_or(0);
+            data.history_origin = (file.history_node(), file.view());
+            data.history_menu.set_items(lines, selected_idx);
+            data.history_nodes = nodes;
+        } else if key.code == KeyCode::Char('n') {
+            data.buffers.open(File::new());
+            data.windows.show_active_buffer(&data.buffers);
+        } else if key.code == KeyCode::Char('w') {
+            let closed_idx = data.buffers.active_idx();
+            data.buffers.close_active();
+            data.windows.remove_buffer(closed_idx, &mut data.buffers);
+        } else if key.code == KeyCode::Char('b') {
+            data.state = State::ListingBuffers;
+            let names = data.buffers.iter().map(|file| String::from(file.display_name())).collect();
+            data.buffer_menu.set_items(names, data.buffers.active_idx());
+        } else if key.code == KeyCode::PageDown {
+            data.buffers.select_next();
+            data.windows.show_active_buffer(&data.buffers);
+        } else if key.code == KeyCode::PageUp {
+            data.buffers.select_previous();
+            data.windows.show_active_buffer(&data.buffers);
+        } else if key.code == KeyCode::Char('o') {
+            data.state = State::Opening;
+            data.open_prompt.set_answer(&UnicodeString::new());
         } else if key.code == KeyCode::Char('s') {
             data.state = State::Saving;
-            data.save_prompt.set_answer(&data.file.path);
+            data.save_encoding = None;
+            data.save_prompt.set_answer(&data.buffers.active().path);
+        }
+    } else if key.modifiers == KeyModifiers::ALT {
+        if key.code == KeyCode::Char('c') {
+            if let Some(text) = data.buffers.active().selected_text() {
+                data.registers.store(text);
+            }
+        } else if key.code == KeyCode::Char('i') {
+            data.state = State::SettingIndentation;
+            data.indentation_prompt.set_answer(&UnicodeString::new());
+        } else if key.code == KeyCode::Char('l') {
+            data.state = State::SettingLineEnding;
+            data.line_ending_prompt.set_answer(&UnicodeString::new());
+        } else if key.code == KeyCode::Char('f') {
+            match data.buffers.active_mut().toggle_final_newline() {
+                Some(true) => data.message = Some(String::from("Saving with a final newline")),
+                Some(false) => data.message = Some(String::from("Saving without a final newline")),
+                None => data.message = Some(String::from("Large files keep the final newline they have on disk")),
+            }
+        } else if key.code == KeyCode::Char('o') {
+            data.state = State::ReopeningWithEncoding;
+            data.encoding_prompt.set_answer(&UnicodeString::new());
+        } else if key.code == KeyCode::Char('s') {
+            data.state = State::SavingWithEncoding;
+            data.encoding_prompt.set_answer(&UnicodeString::new());
+        } else if key.code == KeyCode::Char('n') {
+            data.buffers.active_mut().search_next();
+        } else if key.code == KeyCode::Char('p') {
+            data.buffers.active_mut().search_previous();
+        } else if key.code == KeyCode::Char('e') {
+            data.state = State::ProjectReplacing;
+        } else if key.code == KeyCode::Char('g') {
+            if data.grep.is_some() {
+                data.state = State::BrowsingGrep;
+            }
+        } else if key.code == KeyCode::Char('z') {
+            data.buffers.active_mut().history_earlier();
+        } else if key.code == KeyCode::Char('y') {
+            data.buffers.active_mut().history_later();
+        } else if key.code == KeyCode::Char('v') || key.code == KeyCode::Char('h') {
+            let direction = if key.code == KeyCode::Char('v') { Direction::Horizontal } else { Direction::Vertical };
+            if !data.windows.split(direction, &data.buffers) {
+                data.message = Some(String::from("Not enough room to split the pane"));
+            }
+        } else if key.code == KeyCode::Char('q') {
+            data.windows.close(&mut data.buffers);
+        } else if key.code == KeyCode::Left {
+            data.windows.focus(Direction::Horizontal, false, &mut data.buffers);
+        } else if key.code == KeyCode::Right {
+            data.windows.focus(Direction::Horizontal, true, &mut data.buffers);
+        } else if key.code == KeyCode::Up {
+            data.windows.focus(Direction::Vertical, false, &mut data.buffers);
+        } else if key.code == KeyCode::Down {
+            data.windows.focus(Direction::Vertical, true, &mut data.buffers);
+        }
+    } else if key.modifiers == KeyModifiers::ALT | KeyModifiers::SHIFT {
+        if key.code == KeyCode::Left {
+            data.windows.resize(Direction::Horizontal, -RESIZE_STEP);
+        } else if key.code == KeyCode::Right {
+            data.windows.resize(Direction::Horizontal, RESIZE_STEP);
+        } else if key.code == KeyCode::Up {
+            data.windows.resize(Direction::Vertical, -RESIZE_STEP);
+        } else if key.code == KeyCode::Down {
+            data.windows.resize(Direction::Vertical, RESIZE_STEP);
         }
     } else {
-        if key.code == KeyCode::Up {
-            data.file.move_cursor_up();
+        let select = key.modifiers == KeyModifiers::SHIFT;
+        if key.code == KeyCode::F(3) {
+            if select {
+                data.buffers.active_mut().search_previous();
+            } else {
+                data.buffers.active_mut().search_next();
+            }
+        } else if key.code == KeyCode::Esc {
+            data.buffers.active_mut().clear_search();
+        } else if key.code == KeyCode::Up {
+            data.buffers.active_mut().move_cursor_up(select);
         } else if key.code == KeyCode::Down {
-            data.file.move_cursor_down();
+            data.buffers.active_mut().move_cursor_down(select);
         } else if key.code == KeyCode::Left {
-            data.file.move_cursor_left();
+            data.buffers.active_mut().move_cursor_left(select);
         } else if key.code == KeyCode::Right {
-            data.file.move_cursor_right();
-        }
-
-        // TODO: add tabs
-        else if key.code == KeyCode::Backspace {
-            data.file.remove_character_before();
+            data.buffers.active_mut().move_cursor_right(select);
+        } else if key.code == KeyCode::Home {
+            data.buffers.active_mut().move_cursor_home(select);
+        } else if key.code == KeyCode::End {
+            data.buffers.active_mut().move_cursor_end(select);
+        } else if key.code == KeyCode::PageUp {
+            data.buffers.active_mut().move_cursor_page_up(select);
+        } else if key.code == KeyCode::PageDown {
+            data.buffers.active_mut().move_cursor_page_down(select);
+        } else if key.code == KeyCode::Tab {
+            data.buffers.active_mut().insert_tab();
+        } else if key.code == KeyCode::BackTab {
+            data.buffers.active_mut().dedent();
+        } else if key.code == KeyCode::Backspace {
+            data.buffers.active_mut().remove_character_before();
         } else if key.code == KeyCode::Delete {
-            data.file.remove_character_after();
+            data.buffers.active_mut().remove_character_after();
         } else if key.code == KeyCode::Enter {
-            data.file.break_line();
+            data.buffers.active_mut().break_line();
         } else if let KeyCode::Char(ch) = key.code {
-            data.file.write_character(ch);
+            data.buffers.active_mut().write_character(ch);
         }
     }
 }
 
-fn handle_save_key(key: KeyEvent, data: &mut Data) {
+fn handle_register_key(key: KeyEvent, data: &mut Data) {
+    if let KeyCode::Char(name) = key.code {
+        if name.is_ascii_alphanumeric() && key.modifiers & KeyModifiers::CONTROL == KeyModifiers::NONE {
+            data.registers.select(name);
+        }
+    }
+    data.state = State::Editing;
+}
+
+/// Handles the search option toggles shared by the search and replace prompts. Returns whether `key` was one.
+fn toggle_search_option(key: KeyEvent, data: &mut Data) -> bool {
+    if key.modifiers != KeyModifiers::ALT {
+        return false;
+    }
+
+    let options = &mut data.search_options;
+    match key.code {
+        KeyCode::Char('r') => options.regex = !options.regex,
+        KeyCode::Char('c') => options.case_sensitive = !options.case_sensitive,
+        KeyCode::Char('w') => options.whole_word = !options.whole_word,
+        _ => return false,
+    }
+    return true;
+}
+
+fn cancel_search(data: &mut Data) {
+    let file = data.buffers.active_mut();
+    file.clear_search();
+    file.set_view(data.search_origin.0);
+    data.state = State::Editing;
+}
+
+fn handle_search_key(key: KeyEvent, data: &mut Data) {
+    let cancel = key.code == KeyCode::Esc || key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c');
+
+    if cancel {
+        cancel_search(data);
+    } else if key.code == KeyCode::Enter {
+        data.state = State::Editing;
+    } else if key.modifiers == KeyModifiers::ALT && key.code == KeyCode::Char('n') || key.code == KeyCode::Down {
+        data.buffers.active_mut().search_next();
+    } else if key.modifiers == KeyModifiers::ALT && key.code == KeyCode::Char('p') || key.code == KeyCode::Up {
+        data.buffers.active_mut().search_previous();
+    } else if toggle_search_option(key, data) {
+        search_incrementally(data, State::Searching);
+    } else if key.modifiers & KeyModifiers::CONTROL == KeyModifiers::NONE {
+        edit_prompt(key, &mut data.search_prompt);
+        search_incrementally(data, State::Searching);
+    }
+}
+
+/// Moves to the first match from where the search started, or back there if nothing matches.
+fn search_incrementally(data: &mut Data, state: State) {
+    let pattern = match state {
+        State::Replacing => data.replace_prompt.get_answer(),
+        _ => data.search_prompt.get_answer(),
+    };
+
+    let (origin_view, origin_cursor) = data.search_origin;
+    let file = data.buffers.active_mut();
+    if !file.search(pattern.as_str(), data.search_options, origin_cursor) {
+        file.set_view(origin_view);
+    }
+}
+
+fn handle_replace_key(key: KeyEvent, data: &mut Data) {
+    let cancel = key.code == KeyCode::Esc || key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c');
+
+    if cancel {
+        cancel_search(data);
+    } else if key.code == KeyCode::Enter {
+        data.state = State::ReplacingWith;
+    } else if toggle_search_option(key, data) {
+        search_incrementally(data, State::Replacing);
+    } else if key.modifiers & KeyModifiers::CONTROL == KeyModifiers::NONE {
+        edit_prompt(key, &mut data.replace_prompt);
+        search_incrementally(data, State::Replacing);
+    }
+}
+
+fn handle_replace_with_key(key: KeyEvent, data: &mut Data) {
+    let cancel = key.code == KeyCode::Esc || key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c');
+
+    if cancel {
+        cancel_search(data);
+    } else if key.code == KeyCode::Enter {
+        let file = data.buffers.active_mut();
+        file.set_view(data.search_origin.0);
+        let pattern = data.replace_prompt.get_answer().as_str();
+        let replacement = data.replace_with_prompt.get_answer().as_str();
+
+        data.replace_session = file.start_replace(pattern, data.search_options, replacement);
+        if data.replace_session.is_some() {
+            data.state = State::ConfirmingReplace;
+        } else {
+            data.message = Some(String::from("Nothing to replace"));
+            data.state = State::Editing;
+        }
+    } else if key.modifiers & KeyModifiers::CONTROL == KeyModifiers::NONE {
+        edit_prompt(key, &mut data.replace_with_prompt);
+    }
+}
+
+fn handle_confirm_replace_key(key: KeyEvent, data: &mut Data) {
+    let session = match &mut data.replace_session {
+        Some(session) => session,
+        None => {
+            data.state = State::Editing;
+            return;
+        },
+    };
+    let file = data.buffers.active_mut();
+
+    let more_matches = match key.code {
+        KeyCode::Char('y') => file.replace_current(session),
+        KeyCode::Char('n') => file.skip_current(session),
+        KeyCode::Char('a') => {
+            file.replace_all(session);
+            false
+        },
+        KeyCode::Char('q') | KeyCode::Esc => false,
+        KeyCode::Char('c') if key.modifiers == KeyModifiers::CONTROL => false,
+        _ => true,
+    };
+
+    if !more_matches {
+        file.finish_replace();
+        data.message = Some(format!("Replaced {} occurrence(s)", session.replaced));
+        data.replace_session = None;
+        data.state = State::Editing;
+    }
+}
+
+fn handle_grep_key(key: KeyEvent, data: &mut Data) {
+    let cancel = key.code == KeyCode::Esc || key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c');
+
+    if cancel {
+        data.state = State::Editing;
+    } else if key.code == KeyCode::Enter {
+        let pattern = data.grep_prompt.get_answer().as_str();
+        match data.search_options.build_regex(pattern) {
+            Ok(regex) if !pattern.is_empty() => {
+                data.grep = Some(Grep::start(pattern, regex, PathBuf::from(".")));
+                data.grep_menu.set_items(Vec::new(), 0);
+                data.state = State::BrowsingGrep;
+            },
+            Ok(_) => data.state = State::Editing,
+            Err(_) => {
+                data.message = Some(String::from("Invalid regex"));
+                data.state = State::Editing;
+            },
+        }
+    } else if !toggle_search_option(key, data) && key.modifiers & KeyModifiers::CONTROL == KeyModifiers::NONE {
+        edit_prompt(key, &mut data.grep_prompt);
+    }
+}
+
+fn handle_grep_results_key(key: KeyEvent, data: &mut Data) {
+    let cancel = key.code == KeyCode::Esc || key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c');
+
+    if cancel {
+        data.state = State::Editing;
+    } else if key.code == KeyCode::Up {
+        data.grep_menu.select_previous();
+    } else if key.code == KeyCode::Down {
+        data.grep_menu.select_next();
+    } else if key.code == KeyCode::Enter {
+        let selected = data.grep.as_ref().zip(data.grep_menu.get_selected()).and_then(|(grep, idx)| grep.matches.get(idx));
+        if let Some(found) = selected {
+            match File::open(&found.path.to_string_lossy()) {
+                Ok(file) => {
+                    data.buffers.open(file);
+                    data.buffers.active_mut().set_cursor(found.char_idx, found.line_idx);
+                    data.windows.show_active_buffer(&data.buffers);
+                },
+                Err(error) => data.message = Some(format!("Could not open file: {}", error)),
+            }
+        }
+        data.state = State::Editing;
+    }
+}
+
+fn handle_project_replace_key(key: KeyEvent, data: &mut Data) {
+    let cancel = key.code == KeyCode::Esc || key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c');
+
+    if cancel {
+        data.state = State::Editing;
+    } else if key.code == KeyCode::Enter {
+        data.state = State::ProjectReplacingWith;
+    } else if !toggle_search_option(key, data) && key.modifiers & KeyModifiers::CONTROL == KeyModifiers::NONE {
+        edit_prompt(key, &mut data.project_replace_prompt);
+    }
+}
+
+fn handle_project_replace_with_key(key: KeyEvent, data: &mut Data) {
+    let cancel = key.code == KeyCode::Esc || key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c');
+
+    if cancel {
+        data.state = State::ProjectReplacing;
+    } else if key.code == KeyCode::Enter {
+        let pattern = data.project_replace_prompt.get_answer().as_str();
+        let replacement = data.project_replace_with_prompt.get_answer().as_str();
+        data.state = State::Editing;
+
+        match data.search_options.build_regex(pattern) {
+            Ok(regex) if !pattern.is_empty() => {
+                let project_replace = ProjectReplace::prepare(&regex, replacement, data.search_options.regex, PathBuf::from("."));
+                if project_replace.is_empty() {
+                    data.message = Some(String::from("Nothing to replace"));
+                } else {
+                    data.project_replace_menu.set_items(project_replace.items(), 0);
+                    data.project_replace = Some(project_replace);
+                    data.state = State::PreviewingReplace;
+                }
+            },
+            Ok(_) => (),
+            Err(_) => data.message = Some(String::from("Invalid regex")),
+        }
+    } else if key.modifiers & KeyModifiers::CONTROL == KeyModifiers::NONE {
+        edit_prompt(key, &mut data.project_replace_with_prompt);
+    }
+}
+
+fn handle_replace_preview_key(key: KeyEvent, data: &mut Data) {
+    let cancel = key.code == KeyCode::Esc || key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c');
+    let project_replace = match &mut data.project_replace {
+        Some(project_replace) => project_replace,
+        None => {
+            data.state = State::Editing;
+            return;
+        },
+    };
+
+    if cancel {
+        data.project_replace = None;
+        data.state = State::Editing;
+    } else if key.code == KeyCode::Up {
+        data.project_replace_menu.select_previous();
+    } else if key.code == KeyCode::Down {
+        data.project_replace_menu.select_next();
+    } else if key.code == KeyCode::Char(' ') {
+        if let Some(row) = data.project_replace_menu.get_selected() {
+            project_replace.toggle(row);
+            data.project_replace_menu.set_items(project_replace.items(), row);
+        }
+    } else if key.code == KeyCode::Enter {
+        // Files open with unsaved changes are left alone, as saving the buffer would undo the replacement
+        let edited: Vec<PathBuf> = data.buffers.iter()
+            .filter(|file| file.differs_from_disk())
+            .filter_map(|file| fs::canonicalize(file.path.as_str()).ok())
+            .collect();
+        let (report, written) = project_replace.apply(|path| fs::canonicalize(path).is_ok_and(|path| edited.contains(&path)));
+        data.buffers.reload(&written);
+        data.report_menu.set_items(report, 0);
+        data.project_replace = None;
+        data.state = State::ReadingReport;
+    }
+}
+
+fn handle_report_key(key: KeyEvent, data: &mut Data) {
+    if key.code == KeyCode::Up {
+        data.report_menu.select_previous();
+    } else if key.code == KeyCode::Down {
+        data.report_menu.select_next();
+    } else {
+        data.state = State::Editing;
+    }
+}
+
+fn handle_open_key(key: KeyEvent, data: &mut Data) {
     if key.modifiers == KeyModifiers::CONTROL {
         if key.code == KeyCode::Char('c') {
             data.state = State::Editing;
         }
     } else {
-        if key.code == KeyCode::Left {
-            data.save_prompt.move_cursor_left();
-        } else if key.code == KeyCode::Right {
-            data.save_prompt.move_cursor_right();
+        if key.code == KeyCode::Tab {
+            data.open_prompt.complete_path();
+        } else if key.code == KeyCode::Enter {
+            match File::open(data.open_prompt.get_answer().as_str()) {
+                Ok(file) => {
+                    data.buffers.open(file);
+                    data.windows.show_active_buffer(&data.buffers);
+                },
+                Err(error) => data.message = Some(format!("Could not open file: {}", error)),
+            }
+            data.state = State::Editing;
+        } else {
+            edit_prompt(key, &mut data.open_prompt);
         }
+    }
+}
 
-        else if key.code == KeyCode::Backspace {
-            data.save_prompt.remove_character_before();
-        } else if key.code == KeyCode::Delete {
-            data.save_prompt.remove_character_after();
-        } else if let KeyCode::Char(ch) = key.code {
-            data.save_prompt.write_character(ch);
+fn handle_buffer_list_key(key: KeyEvent, data: &mut Data) {
+    if key.modifiers == KeyModifiers::CONTROL {
+        if key.code == KeyCode::Char('c') {
+            data.state = State::Editing;
+        }
+    } else {
+        if key.code == KeyCode::Up {
+            data.buffer_menu.select_previous();
+        } else if key.code == KeyCode::Down {
+            data.buffer_menu.select_next();
+        } else if key.code == KeyCode::Esc {
+            data.state = State::Editing;
+        } else if key.code == KeyCode::Enter {
+            if let Some(idx) = data.buffer_menu.get_selected() {
+                data.buffers.select(idx);
+                data.windows.show_active_buffer(&data.buffers);
+            }
+            data.state = State::Editing;
+        }
+    }
+}
+
+fn parse_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+    let (amount, unit) = match input.find(|ch: char| !ch.is_ascii_digit()) {
+        Some(idx) => input.split_at(idx),
+        None => (input, "s"),
+    };
+
+    let amount: u64 = amount.parse().ok()?;
+    let seconds = match unit.trim() {
+        "s" => amount,
+        "m" => amount.checked_mul(60)?,
+        "h" => amount.checked_mul(60 * 60)?,
+        _ => return None,
+    };
+    return Some(Duration::from_secs(seconds));
+}
+
+fn handle_time_travel_key(key: KeyEvent, data: &mut Data) {
+    if key.modifiers == KeyModifiers::CONTROL {
+        if key.code == KeyCode::Char('c') {
+            data.state = State::Editing;
+        }
+    } else {
+        if key.code == KeyCode::Enter {
+            match parse_duration(data.time_prompt.get_answer().as_str()) {
+                Some(duration) => data.buffers.active_mut().goto_history_ago(duration),
+                None => data.message = Some(String::from("Invalid duration, expected e.g. 30s, 5m or 1h")),
+            }
+            data.state = State::Editing;
+        } else {
+            edit_prompt(key, &mut data.time_prompt);
+        }
+    }
+}
+
+fn handle_indentation_key(key: KeyEvent, data: &mut Data) {
+    if key.modifiers == KeyModifiers::CONTROL {
+        if key.code == KeyCode::Char('c') {
+            data.state = State::Editing;
+        }
+    } else {
+        if key.code == KeyCode::Enter {
+            let file = data.buffers.active_mut();
+            match file.indentation().parse(data.indentation_prompt.get_answer().as_str()) {
+                Some(indentation) => file.set_indentation(indentation),
+                None => data.message = Some(String::from("Invalid indentation, expected e.g. tabs, spaces 2 or 4")),
+            }
+            data.state = State::Editing;
+        } else {
+            edit_prompt(key, &mut data.indentation_prompt);
+        }
+    }
+}
+
+fn handle_line_ending_key(key: KeyEvent, data: &mut Data) {
+    if key.modifiers == KeyModifiers::CONTROL {
+        if key.code == KeyCode::Char('c') {
+            data.state = State::Editing;
+        }
+    } else {
+        if key.code == KeyCode::Enter {
+            match LineEnding::parse(data.line_ending_prompt.get_answer().as_str()) {
+                Some(line_ending) => if !data.buffers.active_mut().set_line_ending(line_ending) {
+                    data.message = Some(String::from("Large files keep the line endings they have on disk"));
+                },
+                None => data.message = Some(String::from("Invalid line ending, expected lf, crlf or cr")),
+            }
+            data.state = State::Editing;
+        } else {
+            edit_prompt(key, &mut data.line_ending_prompt);
+        }
+    }
+}
+
+fn handle_encoding_key(key: KeyEvent, data: &mut Data) {
+    if key.modifiers == KeyModifiers::CONTROL {
+        if key.code == KeyCode::Char('c') {
+            data.state = State::Editing;
         }
+    } else {
+        if key.code == KeyCode::Enter {
+            let encoding = match Encoding::parse(data.encoding_prompt.get_answer().as_str()) {
+                Some(encoding) => encoding,
+                None => {
+                    data.message = Some(String::from("Unknown encoding, expected e.g. utf-8, utf-16le or windows-1252"));
+                    data.state = State::Editing;
+                    return;
+                },
+            };
+
+            if data.state == State::SavingWithEncoding {
+                if data.buffers.active().is_large() {
+                    data.message = Some(String::from("Large files are always saved as UTF-8"));
+                    data.state = State::Editing;
+                } else {
+                    data.save_encoding = Some(encoding);
+                    data.state = State::Saving;
+                    data.save_prompt.set_answer(&data.buffers.active().path);
+                }
+                return;
+            }
 
-        else if key.code == KeyCode::Enter {
+            // Reopening discards the buffer's changes, as they cannot be mapped onto the newly decoded text
+            data.state = State::Editing;
+            let path = String::from(data.buffers.active().path.as_str());
+            if path.is_empty() {
+                data.message = Some(String::from("The buffer has no file to reopen"));
+                return;
+            }
+            match File::open_with_encoding(&path, Some(encoding)) {
+                Ok(file) => data.buffers.replace_active(file),
+                Err(error) => data.message = Some(format!("Could not reopen file: {}", error)),
+            }
+        } else {
+            edit_prompt(key, &mut data.encoding_prompt);
+        }
+    }
+}
+
+fn handle_history_key(key: KeyEvent, data: &mut Data) {
+    let cancel = key.code == KeyCode::Esc || key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c');
+
+    if cancel {
+        let (node, view) = data.history_origin;
+        let file = data.buffers.active_mut();
+        file.goto_history(node);
+        file.set_view(view);
+        data.state = State::Editing;
+    } else if key.code == KeyCode::Enter {
+        data.state = State::Editing;
+    } else if key.code == KeyCode::Up || key.code == KeyCode::Down {
+        if key.code == KeyCode::Up {
+            data.history_menu.select_previous();
+        } else {
+            data.history_menu.select_next();
+        }
+
+        // Preview the selected state, it is only kept if confirmed with Enter
+        if let Some(idx) = data.history_menu.get_selected() {
+            data.buffers.active_mut().goto_history(data.history_nodes[idx]);
+        }
+    }
+}
+
+fn handle_save_key(key: KeyEvent, data: &mut Data) {
+    if key.modifiers == KeyModifiers::CONTROL {
+        if key.code == KeyCode::Char('c') {
+            data.state = State::Editing;
+            data.save_encoding = None;
+        }
+    } else {
+        if key.code == KeyCode::Tab {
+            data.save_prompt.complete_path();
+        } else if key.code == KeyCode::Enter {
             match save(data, false) {
                 Ok(true) => {
                     data.state = State::Editing;
-                    data.file.path = data.save_prompt.get_answer().clone();
+                    data.buffers.active_mut().path = data.save_prompt.get_answer().clone();
                 },
                 Ok(false) => {
                     data.state = State::Overwriting;
                     data.overwrite_prompt.set_answer(&UnicodeString::new());
                 },
-                Err(error) => panic!("Error on saving file!"), // TODO: handle errors properly
+                Err(error) => {
+                    data.state = State::Editing;
+                    data.message = Some(format!("Could not save file: {}", error));
+                },
             }
-        } 
+        } else {
+            edit_prompt(key, &mut data.save_prompt);
+        }
     }
 }
 
@@ -82,43 +693,102 @@ fn handle_overwrite_key(key: KeyEvent, data: &mut Data) {
             data.state = State::Saving;
         }
     } else {
-        if key.code == KeyCode::Left {
-            data.overwrite_prompt.move_cursor_left();
-        } else if key.code == KeyCode::Right {
-            data.overwrite_prompt.move_cursor_right();
-        }
-
-        else if key.code == KeyCode::Backspace {
-            data.overwrite_prompt.remove_character_before();
-        } else if key.code == KeyCode::Delete {
-            data.overwrite_prompt.remove_character_after();
-        } else if let KeyCode::Char(ch) = key.code {
-            data.overwrite_prompt.write_character(ch);
-        }
-
-        else if key.code == KeyCode::Enter {
+        if key.code == KeyCode::Enter {
             if data.overwrite_prompt.get_answer().as_str().to_lowercase() == "y" {
                 match save(data, true) {
                     Ok(true) => { 
                         data.state = State::Editing;
-                        data.file.path = data.save_prompt.get_answer().clone();
+                        data.buffers.active_mut().path = data.save_prompt.get_answer().clone();
                     },
                     Ok(false) => panic!("Did not overwrite file!"), // Should not be possible because of force_overwrite
-                    Err(error) => panic!("Error on overwriting file!"), // TODO: handle errors properly
+                    Err(error) => {
+                        data.state = State::Editing;
+                        data.message = Some(format!("Could not save file: {}", error));
+                    },
                 }
             } else {
                 data.state = State::Saving;
             }
-        } 
+        } else {
+            edit_prompt(key, &mut data.overwrite_prompt);
+        }
     }
 }
 
+fn handle_paste(text: &str, data: &mut Data) {
+    let prompt = match data.state {
+        State::Editing => {
+            // Terminals send line breaks in pastes as carriage returns
+            let text = text.replace("\r\n", "\n").replace('\r', "\n");
+            data.buffers.active_mut().insert_str(&text);
+            return;
+        },
+        State::Searching => {
+            data.search_prompt.insert_str(text);
+            search_incrementally(data, State::Searching);
+            return;
+        },
+        State::Replacing => {
+            data.replace_prompt.insert_str(text);
+            search_incrementally(data, State::Replacing);
+            return;
+        },
+        State::ReplacingWith => &mut data.replace_with_prompt,
+        State::Grepping => &mut data.grep_prompt,
+        State::ProjectReplacing => &mut data.project_replace_prompt,
+        State::ProjectReplacingWith => &mut data.project_replace_with_prompt,
+        State::Opening => &mut data.open_prompt,
+        State::Saving => &mut data.save_prompt,
+        State::Overwriting => &mut data.overwrite_prompt,
+        State::TimeTraveling => &mut data.time_prompt,
+        State::SettingIndentation => &mut data.indentation_prompt,
+        State::SettingLineEnding => &mut data.line_ending_prompt,
+        State::ReopeningWithEncoding | State::SavingWithEncoding => &mut data.encoding_prompt,
+        _ => return,
+    };
+    prompt.insert_str(text);
+}
+
 pub fn handle_event(event: Event, data: &mut Data) {
     if let Event::Key(key) = event {
+        data.message = None;
         match data.state {
+            State::SelectingRegister => handle_register_key(key, data),
+            State::Searching => handle_search_key(key, data),
+            State::Replacing => handle_replace_key(key, data),
+            State::ReplacingWith => handle_replace_with_key(key, data),
+            State::ConfirmingReplace => handle_confirm_replace_key(key, data),
+            State::Grepping => handle_grep_key(key, data),
+            State::BrowsingGrep => handle_grep_results_key(key, data),
+            State::ProjectReplacing => handle_project_replace_key(key, data),
+            State::ProjectReplacingWith => handle_project_replace_with_key(key, data),
+            State::PreviewingReplace => handle_replace_preview_key(key, data),
+            State::ReadingReport => handle_report_key(key, data),
+            State::Opening => handle_open_key(key, data),
+            State::ListingBuffers => handle_buffer_list_key(key, data),
+            State::TimeTraveling => handle_time_travel_key(key, data),
+            State::SettingIndentation => handle_indentation_key(key, data),
+            State::SettingLineEnding => handle_line_ending_key(key, data),
+            State::ReopeningWithEncoding | State::SavingWithEncoding => handle_encoding_key(key, data),
+            State::BrowsingHistory => handle_history_key(key, data),
             State::Overwriting => handle_overwrite_key(key, data),
             State::Saving => handle_save_key(key, data),
             _ => handle_edit_key(key, data),
         }
+    } else if let Event::Paste(text) = event {
+        data.message = None;
+        handle_paste(&text, data);
+    }
+}
+
+/// Picks up the results of background work, called once per iteration of the main loop.
+pub fn update(data: &mut Data) {
+    if let Some(grep) = &mut data.grep {
+        let new_count = grep.poll();
+        let new_matches = grep.matches[grep.matches.len() - new_count..].iter().map(|found| found.describe());
+        data.grep_menu.push_items(new_matches);
+
+        let progress = if grep.done { "" } else { ", searching..." };
+        data.grep_menu.set_title(&format!("Project search: {} ({} matches{})", grep.pattern, grep.matches.len(), progress));
     }
-}
\ No newline at end of file
+}
@@ -1,16 +1,271 @@
-use std::{io::{self, Write}, path::Path, fs::File};
+use std::{io::{self, BufWriter, Write}, path::{Path, PathBuf}, fs::{self, File, OpenOptions}, process};
 
 use crate::app::Data;
 
+/// How many symbolic links are followed to find the file to write, like the limit of most systems.
+const MAX_SYMLINKS: usize = 40;
+
+/// How many names are tried for the temporary file when others are already taken.
+const MAX_TEMP_ATTEMPTS: usize = 100;
+
+pub fn write_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    return replace_file(path, |writer| writer.write_all(content));
+}
+
+/// Writes to a temporary file next to `path` that then replaces it, so that the original is never truncated and a
+/// crash or full disk while writing leaves it intact. Also needed for memory-mapped files, which are read from while
+/// they are written. Symbolic links are followed, so that their target is replaced rather than the link.
+pub fn replace_file(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) -> io::Result<()> {
+    let path = resolve_symlinks(path)?;
+    let (temp_path, file) = create_temp(&path)?;
+
+    // The content is never less protected than in the original
+    copy_metadata(&path, &file);
+    let mut writer = BufWriter::new(file);
+    let result = write(&mut writer)
+        .and_then(|()| writer.into_inner().map_err(|error| error.into_error()))
+        .and_then(|file| file.sync_all())
+        .and_then(|()| fs::rename(&temp_path, &path));
+
+    match result {
+        Ok(()) => {
+            sync_parent(&path);
+            return Ok(());
+        },
+        Err(error) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(error);
+        },
+    }
+}
+
+/// Creates a temporary file next to `path`, never opening anything that is already there, such as a planted symbolic
+/// link. Only the user can read it until it gets the permissions of the file it replaces.
+fn create_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    let file_name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    for attempt in 0..MAX_TEMP_ATTEMPTS {
+        let temp_path = path.with_file_name(format!(".{}.mate-save-{}-{}", file_name.to_string_lossy(), process::id(), attempt));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            // A new file has nothing to protect, and gets the usual permissions that the umask leaves of these
+            options.mode(if fs::metadata(path).is_ok() { 0o600 } else { 0o666 });
+        }
+
+        match options.open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    return Err(io::Error::new(io::ErrorKind::AlreadyExists, "could not find a free name for the temporary file"));
+}
+
+/// Follows symbolic links from `path` to the file they point to, which may not exist yet.
+fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_SYMLINKS {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                // Relative targets are relative to the link's directory, and absolute ones replace the path
+                let target = fs::read_link(&path)?;
+                path = path.parent().unwrap_or(Path::new("")).join(target);
+            },
+            _ => return Ok(path),
+        }
+    }
+    return Err(io::Error::other("too many levels of symbolic links"));
+}
+
+/// Gives the file replacing `path` its permissions and, where allowed, its owner and group. Failing to do so should
+/// not fail the save.
+fn copy_metadata(path: &Path, file: &File) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return, // A new file keeps the default permissions
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{fchown, MetadataExt};
+        // Only the superuser may change the owner, but the group can be any the user is a member of
+        if fchown(file, Some(metadata.uid()), Some(metadata.gid())).is_err() {
+            let _ = fchown(file, None, Some(metadata.gid()));
+        }
+    }
+
+    // Changing the owner clears the setuid and setgid bits, so the permissions come after
+    let _ = file.set_permissions(metadata.permissions());
+}
+
+/// Makes the rename durable by syncing the directory it happened in.
+#[cfg(unix)]
+fn sync_parent(path: &Path) {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+}
+
+/// Directories cannot be opened to sync them on other systems.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) {}
+
 pub fn save(data: &mut Data, force_overwrite: bool) -> Result<bool, io::Error> {
     let file_exists = Path::new(data.save_prompt.get_answer().as_str()).exists();
 
-    if file_exists && force_overwrite || !file_exists {
-        let mut file = File::create(data.save_prompt.get_answer().as_str())?;
-        file.write_all(data.file.to_string().as_bytes())?;
+    if force_overwrite || !file_exists {
+        // An encoding requested for this save is only kept by the buffer if the save succeeds
+        let path = Path::new(data.save_prompt.get_answer().as_str());
+        let requested_encoding = data.save_encoding.take();
+        if data.buffers.active().is_large() {
+            // Large files have no persistent undo history, as it is keyed by a hash of the whole content
+            replace_file(path, |writer| data.buffers.active().write_to(writer))?;
+            return Ok(true);
+        }
+
+        let file = data.buffers.active_mut();
+        file.prepare_save(path);
+        let mut format = file.format();
+        format.encoding = requested_encoding.unwrap_or(format.encoding);
+        if let Some((count, first)) = file.unmappable_chars(format.encoding) {
+            file.set_cursor(first.0, first.1);
+            let message = format!("{} char(s) cannot be encoded as {}, the first is at line {}", count, format.encoding.name(), first.1 + 1);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        let content = format.disk_text(file.to_string());
+        write_file(path, &format.encoding.encode(&content)?)?;
+        file.set_encoding(format.encoding);
+
+        // The file itself was saved, so failing to keep its undo history should not fail the save
+        let _ = file.save_history(path, &content);
     } else {
         return Ok(false); // Will not overwrite file
     }
 
     return Ok(true);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// Creates an empty directory for a test, removing what an earlier run left behind.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mate-save-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    /// Lists the names in `dir`, to check that no temporary files are left behind.
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        return names;
+    }
+
+    #[test]
+    fn replaces_the_file_without_leaving_temporary_files() {
+        let dir = test_dir("replace");
+        let path = dir.join("file.txt");
+        fs::write(&path, "old content").unwrap();
+
+        write_file(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        write_file(&dir.join("created.txt"), b"created").unwrap();
+        assert_eq!(entries(&dir), vec!["created.txt", "file.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_original_when_writing_fails() {
+        let dir = test_dir("failure");
+        let path = dir.join("file.txt");
+        fs::write(&path, "original").unwrap();
+
+        let result = replace_file(&path, |writer| {
+            writer.write_all(b"partial")?;
+            return Err(io::Error::other("disk full"));
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        assert_eq!(entries(&dir), vec!["file.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = test_dir("symlinks");
+        fs::create_dir(dir.join("real")).unwrap();
+        fs::write(dir.join("real/file.txt"), "old").unwrap();
+        symlink("real/file.txt", dir.join("relative")).unwrap();
+        symlink(dir.join("relative"), dir.join("absolute")).unwrap();
+        symlink("real/missing.txt", dir.join("dangling")).unwrap();
+
+        assert_eq!(resolve_symlinks(&dir.join("absolute")).unwrap(), dir.join("real/file.txt"));
+        write_file(&dir.join("absolute"), b"new").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("real/file.txt")).unwrap(), "new");
+        assert!(fs::symlink_metadata(dir.join("relative")).unwrap().file_type().is_symlink());
+
+        // A link to a file that does not exist yet creates that file
+        write_file(&dir.join("dangling"), b"created").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("real/missing.txt")).unwrap(), "created");
+        assert!(fs::symlink_metadata(dir.join("dangling")).unwrap().file_type().is_symlink());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fails_on_symlink_loops() {
+        use std::os::unix::fs::symlink;
+
+        let dir = test_dir("loop");
+        symlink("b", dir.join("a")).unwrap();
+        symlink("a", dir.join("b")).unwrap();
+        assert!(write_file(&dir.join("a"), b"content").is_err());
+        assert_eq!(entries(&dir), vec!["a", "b"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("permissions");
+        let path = dir.join("script.sh");
+        for mode in [0o640, 0o755, 0o4755] {
+            fs::write(&path, "old").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            write_file(&path, b"new").unwrap();
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o7777, mode);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_open_existing_temporary_files() {
+        use std::os::unix::fs::symlink;
+
+        let dir = test_dir("planted");
+        let path = dir.join("file.txt");
+        fs::write(&path, "old").unwrap();
+        fs::write(dir.join("victim.txt"), "untouched").unwrap();
+        symlink(dir.join("victim.txt"), dir.join(format!(".file.txt.mate-save-{}-0", process::id()))).unwrap();
+
+        write_file(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("victim.txt")).unwrap(), "untouched");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
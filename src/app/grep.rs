@@ -0,0 +1,119 @@
+use std::{fs, path::{Path, PathBuf}, sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender}}, thread};
+
+use ignore::WalkBuilder;
+use regex::Regex;
+
+const MAX_MATCHES: usize = 10_000;
+
+pub struct GrepMatch {
+    pub path: PathBuf,
+    pub line_idx: usize,
+    pub char_idx: usize,
+    pub text: String,
+}
+
+impl GrepMatch {
+    /// Formats the match as `path:line:col: text`, with 1-based line and column.
+    pub fn describe(&self) -> String {
+        return format!("{}:{}:{}: {}", self.path.display(), self.line_idx + 1, self.char_idx + 1, self.text.trim());
+    }
+}
+
+/// A search through every file under a directory, respecting `.gitignore`, running in a background thread.
+pub struct Grep {
+    pub pattern: String,
+    pub matches: Vec<GrepMatch>,
+    pub done: bool,
+    receiver: Receiver<GrepMatch>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Grep {
+    pub fn start(pattern: &str, regex: Regex, root: PathBuf) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let thread_cancelled = Arc::clone(&cancelled);
+        thread::spawn(move || search_tree(&regex, &root, &sender, &thread_cancelled));
+
+        return Self {
+            pattern: String::from(pattern),
+            matches: Vec::new(),
+            done: false,
+            receiver,
+            cancelled,
+        };
+    }
+
+    /// Collects the matches found since the last call. Returns how many there were.
+    pub fn poll(&mut self) -> usize {
+        let previous_count = self.matches.len();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(found) => self.matches.push(found),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.done = true;
+                    break;
+                },
+            }
+        }
+        return self.matches.len() - previous_count;
+    }
+}
+
+impl Drop for Grep {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Calls `f` with the path and content of every UTF-8 file under `root` that is not ignored by `.gitignore`.
+/// Paths are relative to `root`. Stops when `f` returns false or `cancelled` is set.
+pub fn walk_text_files(root: &Path, cancelled: &AtomicBool, mut f: impl FnMut(&Path, &str) -> bool) {
+    for entry in WalkBuilder::new(root).build().flatten() {
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+
+        // Binary and non UTF-8 files are skipped
+        let content = match fs::read(entry.path()).ok().and_then(|bytes| String::from_utf8(bytes).ok()) {
+            Some(content) => content,
+            None => continue,
+        };
+        let path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if !f(path, &content) {
+            return;
+        }
+    }
+}
+
+fn search_tree(regex: &Regex, root: &Path, sender: &Sender<GrepMatch>, cancelled: &AtomicBool) {
+    let mut match_count = 0;
+
+    walk_text_files(root, cancelled, |path, content| {
+        for (line_idx, line) in content.split('\n').enumerate() {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            for found in regex.find_iter(line).filter(|found| !found.is_empty()) {
+                let found_match = GrepMatch {
+                    path: path.to_path_buf(),
+                    line_idx,
+                    char_idx: line[..found.start()].chars().count(),
+                    text: String::from(line),
+                };
+                if sender.send(found_match).is_err() {
+                    return false; // Nobody is listening anymore
+                }
+
+                match_count += 1;
+                if match_count >= MAX_MATCHES {
+                    return false;
+                }
+            }
+        }
+        return true;
+    });
+}
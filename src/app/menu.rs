@@ -0,0 +1,68 @@
+use tui::{layout::Rect, backend::Backend, Frame, widgets::{Block, Borders, Clear, List, ListItem, ListState}, style::{Style, Modifier}};
+
+pub struct Menu {
+    title: String,
+    items: Vec<String>,
+    state: ListState,
+}
+
+impl Menu {
+    pub fn new(title: &str) -> Self {
+        return Self {
+            title: String::from(title),
+            items: Vec::new(),
+            state: ListState::default(),
+        };
+    }
+
+    pub fn set_items(&mut self, items: Vec<String>, selected_idx: usize) {
+        self.state.select(if items.is_empty() { None } else { Some(selected_idx.min(items.len() - 1)) });
+        self.items = items;
+    }
+
+    /// Appends items, keeping the current selection.
+    pub fn push_items(&mut self, items: impl Iterator<Item = String>) {
+        self.items.extend(items);
+        if self.state.selected().is_none() && !self.items.is_empty() {
+            self.state.select(Some(0));
+        }
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.title = String::from(title);
+    }
+
+    pub fn get_selected(&self) -> Option<usize> {
+        return self.state.selected();
+    }
+
+    // Functionality
+
+    pub fn select_previous(&mut self) {
+        if let Some(idx) = self.state.selected() {
+            if idx > 0 {
+                self.state.select(Some(idx - 1));
+            }
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        if let Some(idx) = self.state.selected() {
+            if idx + 1 < self.items.len() {
+                self.state.select(Some(idx + 1));
+            }
+        }
+    }
+
+    // Rendering
+
+    pub fn render<B: Backend>(&mut self, frame: &mut Frame<B>, rect: Rect) {
+        let items: Vec<ListItem> = self.items.iter().map(|item| ListItem::new(item.as_str())).collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(self.title.as_str()))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        frame.render_widget(Clear, rect);
+        frame.render_stateful_widget(list, rect, &mut self.state);
+    }
+}
@@ -0,0 +1,180 @@
+use std::{fs, path::{Path, PathBuf}, sync::atomic::AtomicBool};
+
+use regex::{Captures, Regex};
+
+use super::{functionality::save::write_file, grep::walk_text_files};
+
+/// A proposed change to a single line.
+pub struct Hunk {
+    line_idx: usize,
+    old_line: String,
+    new_line: String,
+    accepted: bool,
+}
+
+pub struct FileChanges {
+    path: PathBuf,
+    hunks: Vec<Hunk>,
+}
+
+/// A search and replace across every file under a directory, previewed hunk by hunk before anything is written.
+pub struct ProjectReplace {
+    root: PathBuf,
+    files: Vec<FileChanges>,
+    rows: Vec<(usize, Option<usize>)>, // (file, hunk) shown on each row of the preview, where `None` is the file header
+}
+
+impl ProjectReplace {
+    /// Finds the changes under `root`. With `expand`, `$1` and `${name}` in `replacement` refer to capture groups,
+    /// otherwise it is inserted literally.
+    pub fn prepare(regex: &Regex, replacement: &str, expand: bool, root: PathBuf) -> Self {
+        let mut files = Vec::new();
+        walk_text_files(&root, &AtomicBool::new(false), |path, content| {
+            let hunks: Vec<Hunk> = content.split('\n').enumerate()
+                .filter_map(|(line_idx, line)| {
+                    let new_line = replace_line(regex, replacement, expand, line)?;
+                    return Some(Hunk { line_idx, old_line: String::from(line), new_line, accepted: true });
+                })
+                .collect();
+
+            if !hunks.is_empty() {
+                files.push(FileChanges { path: path.to_path_buf(), hunks });
+            }
+            return true;
+        });
+
+        let mut rows = Vec::new();
+        for (file_idx, file) in files.iter().enumerate() {
+            rows.push((file_idx, None));
+            rows.extend((0..file.hunks.len()).map(|hunk_idx| (file_idx, Some(hunk_idx))));
+        }
+
+        return Self { root, files, rows };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.files.is_empty();
+    }
+
+    /// Describes each row of the preview as a diff of the affected line.
+    pub fn items(&self) -> Vec<String> {
+        return self.rows.iter().map(|(file_idx, hunk_idx)| {
+            let file = &self.files[*file_idx];
+            match hunk_idx {
+                None => {
+                    let accepted = file.hunks.iter().filter(|hunk| hunk.accepted).count();
+                    return format!("{} ({} of {} changes)", file.path.display(), accepted, file.hunks.len());
+                },
+                Some(hunk_idx) => {
+                    let hunk = &file.hunks[*hunk_idx];
+                    let checkbox = if hunk.accepted { "[x]" } else { "[ ]" };
+                    let indent = " ".repeat(checkbox.len() + (hunk.line_idx + 1).to_string().len() + 3);
+                    return format!(
+                        "  {} {}: -{}\n  {}+{}",
+                        checkbox, hunk.line_idx + 1, hunk.old_line.trim_end(), indent, hunk.new_line.trim_end(),
+                    );
+                },
+            }
+        }).collect();
+    }
+
+    /// Toggles whether the hunk on `row` is accepted. Toggling a file header toggles all of its hunks.
+    pub fn toggle(&mut self, row: usize) {
+        let (file_idx, hunk_idx) = self.rows[row];
+        let file = &mut self.files[file_idx];
+        match hunk_idx {
+            Some(hunk_idx) => file.hunks[hunk_idx].accepted = !file.hunks[hunk_idx].accepted,
+            None => {
+                let accept = !file.hunks.iter().all(|hunk| hunk.accepted);
+                for hunk in &mut file.hunks {
+                    hunk.accepted = accept;
+                }
+            },
+        }
+    }
+
+    /// Writes all accepted hunks, except to files for which `skip` is true. Returns a report with a line per touched
+    /// file, and the paths of the files that were written.
+    pub fn apply(&self, skip: impl Fn(&Path) -> bool) -> (Vec<String>, Vec<PathBuf>) {
+        let mut report = Vec::new();
+        let mut written = Vec::new();
+        for file in &self.files {
+            let accepted_count = file.hunks.iter().filter(|hunk| hunk.accepted).count();
+            if accepted_count == 0 {
+                continue;
+            }
+
+            let path = self.root.join(&file.path);
+            let result = if skip(&path) {
+                String::from("not changed, open with unsaved changes")
+            } else {
+                match apply_file(&path, file) {
+                    Ok(()) => {
+                        written.push(path);
+                        format!("{} replaced", accepted_count)
+                    },
+                    Err(error) => format!("not changed, {}", error),
+                }
+            };
+            report.push(format!("{}: {}", file.path.display(), result));
+        }
+
+        if report.is_empty() {
+            report.push(String::from("No changes were accepted"));
+        }
+        return (report, written);
+    }
+}
+
+/// Replaces all non-empty matches on `line`, returning `None` if there are none.
+fn replace_line(regex: &Regex, replacement: &str, expand: bool, line: &str) -> Option<String> {
+    // Carriage returns are kept out of matching so that they stay at the end of the line
+    let (body, line_end) = match line.strip_suffix('\r') {
+        Some(body) => (body, "\r"),
+        None => (line, ""),
+    };
+
+    if !regex.find_iter(body).any(|found| !found.is_empty()) {
+        return None;
+    }
+
+    let replaced = regex.replace_all(body, |captures: &Captures| {
+        if captures[0].is_empty() {
+            return String::new();
+        } else if !expand {
+            return String::from(replacement);
+        }
+
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+        return expanded;
+    });
+    return Some(format!("{}{}", replaced, line_end));
+}
+
+fn apply_file(path: &Path, file: &FileChanges) -> Result<(), String> {
+    let content = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let mut lines: Vec<String> = content.split('\n').map(String::from).collect();
+
+    for hunk in file.hunks.iter().filter(|hunk| hunk.accepted) {
+        match lines.get_mut(hunk.line_idx) {
+            Some(line) if *line == hunk.old_line => *line = hunk.new_line.clone(),
+            _ => return Err(String::from("the file changed since the preview")),
+        }
+    }
+
+    return write_file(path, lines.join("\n").as_bytes()).map_err(|error| error.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_literally_unless_expanding() {
+        let regex = Regex::new(r"(\d+)").unwrap();
+        assert_eq!(replace_line(&regex, "$price", false, "costs 5\r").as_deref(), Some("costs $price\r"));
+        assert_eq!(replace_line(&regex, "${1}0", true, "costs 5").as_deref(), Some("costs 50"));
+        assert_eq!(replace_line(&regex, "$1", true, "no digits"), None);
+    }
+}
@@ -1,12 +1,20 @@
 use tui::{layout::Rect, backend::Backend, Frame, text::{Span, Spans}, widgets::Paragraph};
 
-use super::unicode::UnicodeString;
+use self::completion::{PathCompletion, path_candidates, longest_common_prefix};
+
+use super::unicode::{self, UnicodeString};
+
+mod completion;
+
+/// Answers are short, so tabs in them are displayed with a fixed width.
+const TAB_WIDTH: usize = 4;
 
 pub struct Prompt {
     prompt: UnicodeString,
     answer: UnicodeString,
     local_cursor: usize,
-    viewport_offset: usize,
+    viewport_offset: usize, // Display columns
+    completion: Option<PathCompletion>,
 }
 
 impl Prompt {
@@ -16,11 +24,13 @@ impl Prompt {
             answer: UnicodeString::new(),
             local_cursor: 0,
             viewport_offset: 0,
+            completion: None,
         };
     }
 
     pub fn set_answer(&mut self, new_answer: &UnicodeString) {
         self.answer = new_answer.clone();
+        self.completion = None;
         if self.local_cursor > new_answer.length() {
             self.local_cursor = new_answer.length();
         }
@@ -33,53 +43,91 @@ impl Prompt {
     // Functionality
 
     pub fn write_character(&mut self, ch: char) {
+        self.completion = None;
         let char_idx = self.local_cursor;
         self.answer.insert(char_idx, ch);
         self.local_cursor += 1;
     }
     
+    /// Inserts `text` at the cursor, leaving out line breaks since answers are single line.
+    pub fn insert_str(&mut self, text: &str) {
+        for ch in text.chars().filter(|ch| *ch != '\n' && *ch != '\r') {
+            self.write_character(ch);
+        }
+    }
+
     pub fn remove_character_before(&mut self) {
-        if self.local_cursor > 0 {
-            let char_idx = self.local_cursor - 1;
-            self.answer.remove(char_idx);
-            self.local_cursor -= 1;
+        self.completion = None;
+        let start = self.answer.previous_grapheme(self.local_cursor);
+        for _ in start..self.local_cursor {
+            self.answer.remove(start);
         }
+        self.local_cursor = start;
     }
     
     pub fn remove_character_after(&mut self) {
-        if self.local_cursor < self.answer.length() {
-            let char_idx = self.local_cursor;
-            self.answer.remove(char_idx);
+        self.completion = None;
+        let end = self.answer.next_grapheme(self.local_cursor);
+        for _ in self.local_cursor..end {
+            self.answer.remove(self.local_cursor);
         }
     }
     
     pub fn move_cursor_left(&mut self) {
-        if self.local_cursor > 0 {
-            self.local_cursor -= 1;
-        }
+        self.completion = None;
+        self.local_cursor = self.answer.previous_grapheme(self.local_cursor);
     }
     
     pub fn move_cursor_right(&mut self) {
-        if self.local_cursor < self.answer.length() {
-            self.local_cursor += 1;
+        self.completion = None;
+        self.local_cursor = self.answer.next_grapheme(self.local_cursor);
+    }
+
+    pub fn complete_path(&mut self) {
+        if self.completion.is_none() {
+            let candidates = path_candidates(self.answer.as_str());
+            if candidates.is_empty() {
+                return;
+            }
+
+            // Extend to the longest common prefix first and only start cycling once that is exhausted
+            let prefix = longest_common_prefix(&candidates);
+            if candidates.len() == 1 || prefix.len() > self.answer.as_str().len() {
+                self.answer = UnicodeString::from(prefix.as_str());
+                self.local_cursor = self.answer.length();
+                return;
+            }
+
+            self.completion = Some(PathCompletion::new(candidates));
+        }
+
+        if let Some(completion) = &mut self.completion {
+            self.answer = UnicodeString::from(completion.next());
+            self.local_cursor = self.answer.length();
         }
     }
 
     // Rendering
 
     pub fn adjust_viewport(&mut self, rect: Rect) {
-        if self.local_cursor < self.viewport_offset {
-            let diff = self.viewport_offset - self.local_cursor;
-            self.viewport_offset -= diff;
-        } else if self.local_cursor - self.viewport_offset > (rect.width - 1) as usize {
-            let diff = (self.local_cursor - self.viewport_offset) - (rect.width - 1) as usize;
-            self.viewport_offset += diff;
+        let answer = self.answer.as_str();
+        let cursor_column = unicode::column(answer, self.local_cursor, TAB_WIDTH);
+        let cursor_width = (unicode::column(answer, self.answer.next_grapheme(self.local_cursor), TAB_WIDTH) - cursor_column).max(1);
+
+        if cursor_column < self.viewport_offset {
+            self.viewport_offset = cursor_column;
+        } else if cursor_column + cursor_width > self.viewport_offset + rect.width as usize {
+            self.viewport_offset = (cursor_column + cursor_width).saturating_sub(rect.width as usize);
         }
     } 
 
+    fn cursor_column(&self) -> usize {
+        return unicode::column(self.answer.as_str(), self.local_cursor, TAB_WIDTH);
+    }
+
     pub fn global_cursor(&self, rect: Rect) -> (u16, u16) {
         return (
-            rect.x + (self.local_cursor - self.viewport_offset + self.prompt.length() + 2) as u16, // +2 to account for ": "
+            rect.x + (self.cursor_column() - self.viewport_offset + unicode::width(self.prompt.as_str()) + 2) as u16, // +2 to account for ": "
             rect.y
         );
     }
@@ -87,10 +135,30 @@ impl Prompt {
     pub fn render<B: Backend>(&self, frame: &mut Frame<B>, rect: Rect) {
         let mut span_vec = vec![Span::from(format!("{}: ", self.prompt.as_str()))];
 
-        if self.answer.length() >= self.viewport_offset {
-            span_vec.push(Span::from(&self.answer[self.viewport_offset..]));
-        }
+        // A wide char or tab cut off at the left edge is replaced by padding
+        let (visible_start, padding) = unicode::skip_columns(self.answer.as_str(), self.viewport_offset, TAB_WIDTH);
+        span_vec.push(Span::from(" ".repeat(padding)));
+        span_vec.push(Span::from(unicode::expand_tabs(&self.answer[visible_start..], self.viewport_offset + padding, TAB_WIDTH)));
 
         frame.render_widget(Paragraph::new(Spans::from(span_vec)), rect);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_by_grapheme() {
+        let mut prompt = Prompt::new("Test");
+        prompt.insert_str("a\u{1F1EB}\u{1F1F7}e\u{301}");
+        prompt.remove_character_before();
+        assert_eq!(prompt.get_answer().as_str(), "a\u{1F1EB}\u{1F1F7}");
+
+        prompt.move_cursor_left();
+        prompt.move_cursor_left();
+        prompt.move_cursor_right();
+        prompt.remove_character_after();
+        assert_eq!(prompt.get_answer().as_str(), "a");
+    }
+}
@@ -0,0 +1,66 @@
+use std::{fs, path::Path};
+
+pub struct PathCompletion {
+    candidates: Vec<String>,
+    next_idx: usize,
+}
+
+impl PathCompletion {
+    pub fn new(candidates: Vec<String>) -> Self {
+        return Self { candidates, next_idx: 0 };
+    }
+
+    pub fn next(&mut self) -> &str {
+        let candidate = &self.candidates[self.next_idx];
+        self.next_idx = (self.next_idx + 1) % self.candidates.len();
+        return candidate;
+    }
+}
+
+pub fn path_candidates(input: &str) -> Vec<String> {
+    let (dir, file_prefix) = match input.rfind('/') {
+        Some(idx) => (&input[..=idx], &input[idx + 1..]),
+        None => ("", input),
+    };
+
+    let read_dir = if dir.is_empty() { Path::new(".") } else { Path::new(dir) };
+    let entries = match fs::read_dir(read_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(file_prefix) || name.starts_with('.') && !file_prefix.starts_with('.') {
+            continue;
+        }
+
+        let mut candidate = format!("{}{}", dir, name);
+        if entry.path().is_dir() {
+            candidate.push('/');
+        }
+        candidates.push(candidate);
+    }
+
+    candidates.sort();
+    return candidates;
+}
+
+pub fn longest_common_prefix(strings: &[String]) -> String {
+    let mut prefix = match strings.first() {
+        Some(first) => first.clone(),
+        None => return String::new(),
+    };
+
+    for string in &strings[1..] {
+        let common_length: usize = prefix.chars()
+            .zip(string.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .sum();
+        prefix.truncate(common_length);
+    }
+
+    return prefix;
+}
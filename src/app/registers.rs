@@ -0,0 +1,122 @@
+use std::{collections::HashMap, fmt};
+
+use crossterm::Command;
+
+const BASE64_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(BASE64_ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    return output;
+}
+
+/// Sets the terminal's system clipboard using the OSC 52 escape sequence, which also works over SSH.
+pub struct SetClipboard(pub String);
+
+impl Command for SetClipboard {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        return write!(f, "\x1b]52;c;{}\x07", base64_encode(self.0.as_bytes()));
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> crossterm::Result<()> {
+        return Ok(()); // Legacy Windows consoles have no way to set the clipboard
+    }
+}
+
+/// Clipboard registers. Cut, copy and paste use the default register unless a named one is selected first.
+pub struct Registers {
+    default: String,
+    named: HashMap<char, String>,
+    selected: Option<char>,
+    system_clipboard: bool,
+    pending_system_copy: Option<String>,
+}
+
+impl Registers {
+    pub fn new(system_clipboard: bool) -> Self {
+        return Self {
+            default: String::new(),
+            named: HashMap::new(),
+            selected: None,
+            system_clipboard,
+            pending_system_copy: None,
+        };
+    }
+
+    /// Selects the register used by the next cut, copy or paste.
+    pub fn select(&mut self, name: char) {
+        self.selected = Some(name);
+    }
+
+    pub fn get_selected(&self) -> Option<char> {
+        return self.selected;
+    }
+
+    pub fn store(&mut self, text: String) {
+        match self.selected.take() {
+            Some(name) => { self.named.insert(name, text); },
+            None => {
+                if self.system_clipboard {
+                    self.pending_system_copy = Some(text.clone());
+                }
+                self.default = text;
+            },
+        }
+    }
+
+    pub fn load(&mut self) -> &str {
+        match self.selected.take() {
+            Some(name) => return self.named.get(&name).map_or("", |text| text.as_str()),
+            None => return self.default.as_str(),
+        }
+    }
+
+    /// Returns text that should be copied to the system clipboard, if any.
+    pub fn take_system_copy(&mut self) -> Option<String> {
+        return self.pending_system_copy.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pads_base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"M"), "TQ==");
+        assert_eq!(base64_encode(b"Ma"), "TWE=");
+        assert_eq!(base64_encode(b"Man"), "TWFu");
+        assert_eq!(base64_encode(b"Many"), "TWFueQ==");
+    }
+
+    #[test]
+    fn keeps_named_registers_apart_from_the_default() {
+        let mut registers = Registers::new(true);
+        registers.store(String::from("default"));
+        registers.select('a');
+        registers.store(String::from("named"));
+        assert_eq!(registers.take_system_copy(), Some(String::from("default")));
+
+        // The selection only applies to the next cut, copy or paste
+        assert_eq!(registers.load(), "default");
+        registers.select('a');
+        assert_eq!(registers.load(), "named");
+        registers.select('b');
+        assert_eq!(registers.load(), "");
+        assert!(registers.get_selected().is_none());
+    }
+}
@@ -1,7 +1,18 @@
-use tui::{Frame, backend::Backend, layout::{Layout, Constraint, Direction}, widgets::{Block, Borders}};
+use tui::{Frame, backend::Backend, layout::{Layout, Constraint, Direction, Rect}, widgets::{Block, Borders, Paragraph}};
 
 use super::{Data, State};
 
+fn centered_rect(rect: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(rect.width);
+    let height = height.min(rect.height);
+    return Rect::new(
+        rect.x + (rect.width - width) / 2,
+        rect.y + (rect.height - height) / 2,
+        width,
+        height,
+    );
+}
+
 pub fn render<B: Backend>(frame: &mut Frame<B>, data: &mut Data) {
     let layout = Layout::default()
         .direction(Direction::Vertical)
@@ -12,15 +23,74 @@ pub fn render<B: Backend>(frame: &mut Frame<B>, data: &mut Data) {
         .split(frame.size());
 
     let file_rect = layout[0];
-    data.file.adjust_viewport(file_rect);
-    data.file.render(frame, file_rect);
+    let file_cursor = data.windows.render(frame, file_rect, &mut data.buffers);
 
     let bar_block = Block::default().borders(Borders::TOP);
     let bar_content_rect = bar_block.inner(layout[1]);
 
     match data.state {
         State::Editing => {
-            let cursor = data.file.global_cursor(file_rect);
+            if let Some(message) = &data.message {
+                frame.render_widget(Paragraph::new(message.as_str()), bar_content_rect);
+            } else {
+                let file = data.buffers.active();
+                let mut status = format!(
+                    "[{}/{}] {} | {} | {} | {}",
+                    data.buffers.active_idx() + 1, data.buffers.len(), file.display_name(),
+                    file.indentation().describe(), file.format().encoding.name(), file.line_ending_status(),
+                );
+                if let Some(large_file_status) = data.buffers.active().large_file_status() {
+                    status.push_str(&format!(" | {}", large_file_status));
+                }
+                if let Some(search_status) = data.buffers.active().search_status() {
+                    status.push_str(&format!(" | {}", search_status));
+                }
+                if let Some(name) = data.registers.get_selected() {
+                    status.push_str(&format!(" (register \"{})", name));
+                }
+                frame.render_widget(Paragraph::new(status), bar_content_rect);
+            }
+            frame.set_cursor(file_cursor.0, file_cursor.1);
+        },
+        State::SelectingRegister => {
+            frame.render_widget(Paragraph::new("Register: press a-z or 0-9"), bar_content_rect);
+            frame.set_cursor(file_cursor.0, file_cursor.1);
+        },
+        State::Searching | State::Replacing | State::ReplacingWith | State::Grepping | State::ProjectReplacing | State::ProjectReplacingWith => {
+            let prompt = match data.state {
+                State::Searching => &mut data.search_prompt,
+                State::Replacing => &mut data.replace_prompt,
+                State::Grepping => &mut data.grep_prompt,
+                State::ProjectReplacing => &mut data.project_replace_prompt,
+                State::ProjectReplacingWith => &mut data.project_replace_with_prompt,
+                _ => &mut data.replace_with_prompt,
+            };
+
+            let in_buffer = matches!(data.state, State::Searching | State::Replacing | State::ReplacingWith);
+            let mut status = if !in_buffer { String::new() } else { data.buffers.active().search_status().unwrap_or_default() };
+            let options = data.search_options.describe();
+            if !options.is_empty() {
+                status.push_str(&format!(" [{}]", options));
+            }
+            let status_width = (status.chars().count() as u16 + 1).min(bar_content_rect.width / 2);
+            let prompt_rect = Rect { width: bar_content_rect.width - status_width, ..bar_content_rect };
+            let status_rect = Rect { x: prompt_rect.right(), width: status_width, ..bar_content_rect };
+
+            prompt.adjust_viewport(prompt_rect);
+            prompt.render(frame, prompt_rect);
+            frame.render_widget(Paragraph::new(status), status_rect);
+            let cursor = prompt.global_cursor(prompt_rect);
+            frame.set_cursor(cursor.0, cursor.1);
+        },
+        State::ConfirmingReplace => {
+            let status = data.buffers.active().search_status().unwrap_or_default();
+            frame.render_widget(Paragraph::new(format!("Replace this match? (y)es/(n)o/(a)ll/(q)uit | {}", status)), bar_content_rect);
+            frame.set_cursor(file_cursor.0, file_cursor.1);
+        },
+        State::Opening => {
+            data.open_prompt.adjust_viewport(bar_content_rect);
+            data.open_prompt.render(frame, bar_content_rect);
+            let cursor = data.open_prompt.global_cursor(bar_content_rect);
             frame.set_cursor(cursor.0, cursor.1);
         },
         State::Saving => {
@@ -35,6 +105,52 @@ pub fn render<B: Backend>(frame: &mut Frame<B>, data: &mut Data) {
             let cursor = data.overwrite_prompt.global_cursor(bar_content_rect);
             frame.set_cursor(cursor.0, cursor.1);
         },
+        State::TimeTraveling => {
+            data.time_prompt.adjust_viewport(bar_content_rect);
+            data.time_prompt.render(frame, bar_content_rect);
+            let cursor = data.time_prompt.global_cursor(bar_content_rect);
+            frame.set_cursor(cursor.0, cursor.1);
+        },
+        State::SettingIndentation => {
+            data.indentation_prompt.adjust_viewport(bar_content_rect);
+            data.indentation_prompt.render(frame, bar_content_rect);
+            let cursor = data.indentation_prompt.global_cursor(bar_content_rect);
+            frame.set_cursor(cursor.0, cursor.1);
+        },
+        State::SettingLineEnding => {
+            data.line_ending_prompt.adjust_viewport(bar_content_rect);
+            data.line_ending_prompt.render(frame, bar_content_rect);
+            let cursor = data.line_ending_prompt.global_cursor(bar_content_rect);
+            frame.set_cursor(cursor.0, cursor.1);
+        },
+        State::ReopeningWithEncoding | State::SavingWithEncoding => {
+            data.encoding_prompt.adjust_viewport(bar_content_rect);
+            data.encoding_prompt.render(frame, bar_content_rect);
+            let cursor = data.encoding_prompt.global_cursor(bar_content_rect);
+            frame.set_cursor(cursor.0, cursor.1);
+        },
+        State::BrowsingHistory => {
+            // Kept to the side so that the previewed buffer stays visible
+            let width = (file_rect.width / 3).max(30).min(file_rect.width);
+            let menu_rect = Rect::new(file_rect.right() - width, file_rect.y, width, file_rect.height);
+            data.history_menu.render(frame, menu_rect);
+        },
+        State::BrowsingGrep => {
+            let menu_rect = centered_rect(file_rect, file_rect.width.saturating_sub(4), file_rect.height.saturating_sub(2));
+            data.grep_menu.render(frame, menu_rect);
+        },
+        State::PreviewingReplace => {
+            let menu_rect = centered_rect(file_rect, file_rect.width.saturating_sub(4), file_rect.height.saturating_sub(2));
+            data.project_replace_menu.render(frame, menu_rect);
+        },
+        State::ReadingReport => {
+            let menu_rect = centered_rect(file_rect, file_rect.width.saturating_sub(4), file_rect.height / 2);
+            data.report_menu.render(frame, menu_rect);
+        },
+        State::ListingBuffers => {
+            let menu_rect = centered_rect(file_rect, file_rect.width / 2, file_rect.height / 2);
+            data.buffer_menu.render(frame, menu_rect);
+        },
         _ => (),
     }
 
@@ -1,4 +1,7 @@
-use std::{string::Drain, ops::{Index, RangeFrom}};
+use std::ops::{Index, RangeFrom};
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Clone)]
 pub struct UnicodeString {
@@ -41,12 +44,6 @@ impl UnicodeString {
         self.length += 1;
     }
 
-    pub fn push_str(&mut self, s: &str) {
-        for ch in s.chars() {
-            self.push(ch);
-        }
-    }
-
     pub fn insert(&mut self, idx: usize, ch: char) {
         assert!(idx <= self.length);
 
@@ -65,7 +62,7 @@ impl UnicodeString {
         }
     }
 
-    pub fn remove(&mut self, idx: usize) {
+    pub fn remove(&mut self, idx: usize) -> char {
         assert!(idx < self.length);
 
         let inner_idx = self.inner_indices.remove(idx);
@@ -76,32 +73,17 @@ impl UnicodeString {
         }
 
         self.length -= 1;
+        return ch;
     }
 
-    pub fn drain(&mut self, start: usize, end: usize) -> Drain {
-        assert!(start <= end);
-        assert!(start <= self.length);
-        assert!(end <= self.length);
-        
-        if start == end {
-            return self.inner_string.drain(0..0);
-        } else {
-            let inner_start = self.inner_indices[start];
-            let inner_end = if end == self.length {
-                self.inner_string.len()
-            } else {
-                self.inner_indices[end]
-            };
-
-            let removed_bytes = inner_end - inner_start;
-            self.inner_indices.drain(start..end);
-            for inner_idx in &mut self.inner_indices[start..] {
-                *inner_idx -= removed_bytes;
-            }
+    /// Returns the char index of the next grapheme cluster boundary after `char_idx`.
+    pub fn next_grapheme(&self, char_idx: usize) -> usize {
+        return next_grapheme(self.as_str(), char_idx);
+    }
 
-            self.length -= end - start;
-            return self.inner_string.drain(inner_start..inner_end);
-        }
+    /// Returns the char index of the previous grapheme cluster boundary before `char_idx`.
+    pub fn previous_grapheme(&self, char_idx: usize) -> usize {
+        return previous_grapheme(self.as_str(), char_idx);
     }
 
     pub fn as_str(&self) -> &str {
@@ -127,4 +109,198 @@ impl PartialEq for UnicodeString {
         return self.inner_string == other.inner_string;
         
     }
-}
\ No newline at end of file
+}
+
+/// Returns the char index of the next grapheme cluster boundary after `char_idx` in `s`, or its length at the end.
+pub fn next_grapheme(s: &str, char_idx: usize) -> usize {
+    let mut boundary = 0;
+    for grapheme in s.graphemes(true) {
+        boundary += grapheme.chars().count();
+        if boundary > char_idx {
+            return boundary;
+        }
+    }
+    return boundary;
+}
+
+/// Returns the char index of the previous grapheme cluster boundary before `char_idx` in `s`, or 0 at the start.
+pub fn previous_grapheme(s: &str, char_idx: usize) -> usize {
+    let mut previous = 0;
+    let mut boundary = 0;
+    for grapheme in s.graphemes(true) {
+        boundary += grapheme.chars().count();
+        if boundary >= char_idx {
+            return previous;
+        }
+        previous = boundary;
+    }
+    return previous;
+}
+
+/// Returns the number of terminal columns `s` takes up. Wide chars take two and zero-width chars none.
+pub fn width(s: &str) -> usize {
+    return UnicodeWidthStr::width(s);
+}
+
+/// Returns the number of columns a grapheme cluster at display `column` takes up, where tabs reach the next tab stop.
+fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        return tab_width - column % tab_width;
+    }
+    return width(grapheme);
+}
+
+/// Returns the display column at which the char at `char_idx` starts. Positions past the end of `s` count as
+/// one column per char, so that a column is kept when moving through shorter lines.
+pub fn column(s: &str, char_idx: usize, tab_width: usize) -> usize {
+    let mut column = 0;
+    let mut chars = 0;
+    for grapheme in s.graphemes(true) {
+        let grapheme_chars = grapheme.chars().count();
+        if chars + grapheme_chars > char_idx {
+            return column;
+        }
+        column += grapheme_width(grapheme, column, tab_width);
+        chars += grapheme_chars;
+    }
+    return column + (char_idx - chars);
+}
+
+/// Returns the char index of the grapheme cluster covering `column`, the inverse of `column`.
+pub fn char_at_column(s: &str, column: usize, tab_width: usize) -> usize {
+    let mut grapheme_column = 0;
+    let mut chars = 0;
+    for grapheme in s.graphemes(true) {
+        let grapheme_width = grapheme_width(grapheme, grapheme_column, tab_width);
+        if grapheme_column + grapheme_width > column {
+            return chars;
+        }
+        grapheme_column += grapheme_width;
+        chars += grapheme.chars().count();
+    }
+    return chars + (column - grapheme_column);
+}
+
+/// Returns the char index of the first grapheme cluster starting at or after `column`, and how many columns of
+/// padding are needed in its place when a wide char or tab is cut off at `column`.
+pub fn skip_columns(s: &str, column: usize, tab_width: usize) -> (usize, usize) {
+    let mut grapheme_column = 0;
+    let mut chars = 0;
+    for grapheme in s.graphemes(true) {
+        if grapheme_column >= column {
+            return (chars, grapheme_column - column);
+        }
+        grapheme_column += grapheme_width(grapheme, grapheme_column, tab_width);
+        chars += grapheme.chars().count();
+    }
+    return (chars, grapheme_column.saturating_sub(column));
+}
+
+/// Replaces the tabs in `s`, which starts at display `column`, by spaces up to the next tab stop.
+pub fn expand_tabs(s: &str, column: usize, tab_width: usize) -> String {
+    let mut expanded = String::new();
+    let mut column = column;
+    for grapheme in s.graphemes(true) {
+        let grapheme_width = grapheme_width(grapheme, column, tab_width);
+        if grapheme == "\t" {
+            expanded.extend(std::iter::repeat_n(' ', grapheme_width));
+        } else {
+            expanded.push_str(grapheme);
+        }
+        column += grapheme_width;
+    }
+    return expanded;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMBINING: &str = "e\u{301}"; // e + combining acute accent
+    const FAMILY: &str = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}"; // Man, woman and girl joined by ZWJs
+    const FLAGS: &str = "\u{1F1E9}\u{1F1EA}\u{1F1EB}\u{1F1F7}"; // Two regional indicator pairs
+    const HANGUL: &str = "\u{1100}\u{1161}\u{11A8}"; // Conjoining jamo forming one syllable
+
+    /// Collects the boundaries visited by stepping through `s` with `step`, starting at `start`.
+    fn walk(s: &str, start: usize, step: fn(&str, usize) -> usize) -> Vec<usize> {
+        let mut boundaries = vec![start];
+        loop {
+            let next = step(s, *boundaries.last().unwrap());
+            if next == *boundaries.last().unwrap() {
+                return boundaries;
+            }
+            boundaries.push(next);
+        }
+    }
+
+    #[test]
+    fn steps_over_combining_marks() {
+        let s = format!("a{}b", COMBINING);
+        assert_eq!(walk(&s, 0, next_grapheme), vec![0, 1, 3, 4]);
+        assert_eq!(walk(&s, 4, previous_grapheme), vec![4, 3, 1, 0]);
+    }
+
+    #[test]
+    fn steps_over_zwj_sequences() {
+        let s = format!("{}x{}", FAMILY, FAMILY);
+        assert_eq!(walk(&s, 0, next_grapheme), vec![0, 5, 6, 11]);
+        assert_eq!(walk(&s, 11, previous_grapheme), vec![11, 6, 5, 0]);
+    }
+
+    #[test]
+    fn pairs_regional_indicators() {
+        assert_eq!(walk(FLAGS, 0, next_grapheme), vec![0, 2, 4]);
+        assert_eq!(walk(FLAGS, 4, previous_grapheme), vec![4, 2, 0]);
+    }
+
+    #[test]
+    fn keeps_hangul_syllables_and_crlf_together() {
+        assert_eq!(walk(HANGUL, 0, next_grapheme), vec![0, 3]);
+        assert_eq!(walk("a\r\nb", 0, next_grapheme), vec![0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn snaps_from_inside_a_cluster() {
+        assert_eq!(next_grapheme(FAMILY, 2), 5);
+        assert_eq!(previous_grapheme(FAMILY, 2), 0);
+    }
+
+    #[test]
+    fn unicode_string_delegates() {
+        let s = UnicodeString::from(&format!("{}{}", COMBINING, FLAGS));
+        assert_eq!(s.next_grapheme(0), 2);
+        assert_eq!(s.previous_grapheme(s.length()), 4);
+    }
+
+    #[test]
+    fn measures_wide_and_zero_width_chars() {
+        assert_eq!(width("日本語"), 6);
+        assert_eq!(width(COMBINING), 1);
+        assert_eq!(width("a\u{200B}b"), 2); // Zero-width space
+    }
+
+    #[test]
+    fn converts_between_chars_and_columns() {
+        let s = format!("日{}本", COMBINING);
+        assert_eq!((0..=5).map(|char_idx| column(&s, char_idx, 4)).collect::<Vec<_>>(), vec![0, 2, 2, 3, 5, 6]);
+        assert_eq!((0..=6).map(|column| char_at_column(&s, column, 4)).collect::<Vec<_>>(), vec![0, 0, 1, 3, 3, 4, 5]);
+    }
+
+    #[test]
+    fn pads_wide_chars_cut_off_at_the_viewport() {
+        assert_eq!(skip_columns("日本", 0, 4), (0, 0));
+        assert_eq!(skip_columns("日本", 1, 4), (1, 1));
+        assert_eq!(skip_columns("日本", 2, 4), (1, 0));
+        assert_eq!(skip_columns("日本", 5, 4), (2, 0));
+    }
+
+    #[test]
+    fn expands_tabs_to_tab_stops() {
+        let s = "\ta\tbc\t";
+        assert_eq!((0..=6).map(|char_idx| column(s, char_idx, 4)).collect::<Vec<_>>(), vec![0, 4, 5, 8, 9, 10, 12]);
+        assert_eq!(char_at_column(s, 2, 4), 0);
+        assert_eq!(skip_columns(s, 6, 4), (3, 2));
+        assert_eq!(expand_tabs(s, 0, 4), "    a   bc  ");
+        assert_eq!(expand_tabs("\tx", 3, 4), " x");
+    }
+}
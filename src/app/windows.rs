@@ -0,0 +1,298 @@
+use tui::{layout::{Rect, Layout, Constraint, Direction}, backend::Backend, Frame, widgets::{Block, Borders}};
+
+use super::{buffers::Buffers, file::View};
+
+const MIN_RATIO: u16 = 10;
+const MAX_RATIO: u16 = 90;
+
+/// Panes are not split if either half would be smaller than this.
+const MIN_PANE_WIDTH: u16 = 10;
+const MIN_PANE_HEIGHT: u16 = 3;
+
+#[derive(Clone, Copy)]
+pub struct Pane {
+    pub buffer_idx: usize,
+    view: View,
+}
+
+enum Node {
+    Leaf(Pane),
+    Split {
+        direction: Direction,
+        ratio: u16, // Percentage of the space given to the first node
+        first: Box<Node>,
+        second: Box<Node>,
+    },
+}
+
+impl Node {
+    fn leaf_count(&self) -> usize {
+        match self {
+            Node::Leaf(_) => return 1,
+            Node::Split { first, second, .. } => return first.leaf_count() + second.leaf_count(),
+        }
+    }
+
+    fn leaf_mut(&mut self, idx: usize) -> Option<&mut Pane> {
+        match self {
+            Node::Leaf(pane) => return if idx == 0 { Some(pane) } else { None },
+            Node::Split { first, second, .. } => {
+                let first_count = first.leaf_count();
+                if idx < first_count {
+                    return first.leaf_mut(idx);
+                } else {
+                    return second.leaf_mut(idx - first_count);
+                }
+            },
+        }
+    }
+
+    fn split(self, idx: usize, direction: Direction) -> Node {
+        match self {
+            Node::Leaf(pane) => {
+                assert!(idx == 0);
+                return Node::Split {
+                    direction,
+                    ratio: 50,
+                    first: Box::new(Node::Leaf(pane)),
+                    second: Box::new(Node::Leaf(pane)),
+                };
+            },
+            Node::Split { direction: split_direction, ratio, first, second } => {
+                let first_count = first.leaf_count();
+                let (first, second) = if idx < first_count {
+                    (first.split(idx, direction), *second)
+                } else {
+                    (*first, second.split(idx - first_count, direction))
+                };
+                return Node::Split { direction: split_direction, ratio, first: Box::new(first), second: Box::new(second) };
+            },
+        }
+    }
+
+    /// Returns `None` if the node itself was the closed leaf.
+    fn close(self, idx: usize) -> Option<Node> {
+        match self {
+            Node::Leaf(_) => return None,
+            Node::Split { direction, ratio, first, second } => {
+                let first_count = first.leaf_count();
+                if idx < first_count {
+                    return match first.close(idx) {
+                        Some(first) => Some(Node::Split { direction, ratio, first: Box::new(first), second }),
+                        None => Some(*second),
+                    };
+                } else {
+                    return match second.close(idx - first_count) {
+                        Some(second) => Some(Node::Split { direction, ratio, first, second: Box::new(second) }),
+                        None => Some(*first),
+                    };
+                }
+            },
+        }
+    }
+
+    /// Moves the closest divider in `direction` around the leaf at `idx`. Returns whether a divider was found.
+    fn resize(&mut self, idx: usize, direction: &Direction, delta: i16) -> bool {
+        match self {
+            Node::Leaf(_) => return false,
+            Node::Split { direction: split_direction, ratio, first, second } => {
+                let first_count = first.leaf_count();
+                let resized = if idx < first_count {
+                    first.resize(idx, direction, delta)
+                } else {
+                    second.resize(idx - first_count, direction, delta)
+                };
+
+                if !resized && split_direction == direction {
+                    *ratio = (*ratio as i16 + delta).clamp(MIN_RATIO as i16, MAX_RATIO as i16) as u16;
+                    return true;
+                }
+                return resized;
+            },
+        }
+    }
+
+    fn layout(&self, rect: Rect, pane_rects: &mut Vec<Rect>, separators: &mut Vec<(Rect, Borders)>) {
+        match self {
+            Node::Leaf(_) => pane_rects.push(rect),
+            Node::Split { direction, ratio, first, second } => {
+                let layout = Layout::default()
+                    .direction(direction.clone())
+                    .constraints([
+                        Constraint::Percentage(*ratio),
+                        Constraint::Percentage(100 - *ratio),
+                    ])
+                    .split(rect);
+
+                let borders = if *direction == Direction::Horizontal { Borders::LEFT } else { Borders::TOP };
+                let second_rect = Block::default().borders(borders).inner(layout[1]);
+                separators.push((layout[1], borders));
+
+                first.layout(layout[0], pane_rects, separators);
+                second.layout(second_rect, pane_rects, separators);
+            },
+        }
+    }
+}
+
+pub struct Windows {
+    root: Node,
+    focused_idx: usize,
+    pane_rects: Vec<Rect>,
+}
+
+impl Windows {
+    pub fn new(buffer_idx: usize) -> Self {
+        return Self {
+            root: Node::Leaf(Pane { buffer_idx, view: View::default() }),
+            focused_idx: 0,
+            pane_rects: Vec::new(),
+        };
+    }
+
+    pub fn focused_mut(&mut self) -> &mut Pane {
+        return self.root.leaf_mut(self.focused_idx).expect("focused pane should always exist");
+    }
+
+    // Functionality
+
+    /// Splits the focused pane in two, both showing the same buffer, and focuses the new pane. Returns false if the
+    /// pane is too small to split.
+    pub fn split(&mut self, direction: Direction, buffers: &Buffers) -> bool {
+        // Either half gets at least the minimum size next to the separator between them
+        if let Some(rect) = self.pane_rects.get(self.focused_idx) {
+            let (size, min_size) = match direction {
+                Direction::Horizontal => (rect.width, MIN_PANE_WIDTH),
+                Direction::Vertical => (rect.height, MIN_PANE_HEIGHT),
+            };
+            if size < 2 * min_size + 1 {
+                return false;
+            }
+        }
+
+        self.focused_mut().view = buffers.active().view();
+
+        let root = std::mem::replace(&mut self.root, Node::Leaf(Pane { buffer_idx: 0, view: View::default() }));
+        self.root = root.split(self.focused_idx, direction);
+        self.focused_idx += 1;
+        return true;
+    }
+
+    pub fn close(&mut self, buffers: &mut Buffers) {
+        if self.root.leaf_count() == 1 {
+            return;
+        }
+
+        let root = std::mem::replace(&mut self.root, Node::Leaf(Pane { buffer_idx: 0, view: View::default() }));
+        self.root = root.close(self.focused_idx).expect("root should not be closed when it has several panes");
+        if self.focused_idx >= self.root.leaf_count() {
+            self.focused_idx = self.root.leaf_count() - 1;
+        }
+        self.load_focused(buffers);
+    }
+
+    pub fn focus(&mut self, direction: Direction, forward: bool, buffers: &mut Buffers) {
+        let focused_rect = match self.pane_rects.get(self.focused_idx) {
+            Some(rect) => *rect,
+            None => return,
+        };
+
+        let mut best: Option<(usize, (u16, u16))> = None;
+        for (idx, rect) in self.pane_rects.iter().enumerate() {
+            let (start, end, focused_start, focused_end, offset) = match direction {
+                Direction::Horizontal => (rect.x, rect.right(), focused_rect.x, focused_rect.right(), rect.y.abs_diff(focused_rect.y)),
+                Direction::Vertical => (rect.y, rect.bottom(), focused_rect.y, focused_rect.bottom(), rect.x.abs_diff(focused_rect.x)),
+            };
+
+            let distance = if forward && start >= focused_end {
+                start - focused_end
+            } else if !forward && end <= focused_start {
+                focused_start - end
+            } else {
+                continue;
+            };
+
+            if best.is_none_or(|(_, best_score)| (distance, offset) < best_score) {
+                best = Some((idx, (distance, offset)));
+            }
+        }
+
+        if let Some((idx, _)) = best {
+            self.focused_mut().view = buffers.active().view();
+            self.focused_idx = idx;
+            self.load_focused(buffers);
+        }
+    }
+
+    pub fn resize(&mut self, direction: Direction, delta: i16) {
+        self.root.resize(self.focused_idx, &direction, delta);
+    }
+
+    /// Keeps the buffer indices of all panes valid after the buffer at `closed_idx` has been removed.
+    pub fn remove_buffer(&mut self, closed_idx: usize, buffers: &mut Buffers) {
+        for idx in 0..self.root.leaf_count() {
+            let pane = self.root.leaf_mut(idx).expect("index should be within leaf count");
+            if pane.buffer_idx > closed_idx {
+                pane.buffer_idx -= 1;
+            } else if pane.buffer_idx == closed_idx {
+                pane.buffer_idx = closed_idx.min(buffers.len() - 1);
+                pane.view = buffers.get_mut(pane.buffer_idx).view();
+            }
+        }
+        self.load_focused(buffers);
+    }
+
+    /// Points the focused pane at the active buffer, e.g. after switching or opening buffers.
+    pub fn show_active_buffer(&mut self, buffers: &Buffers) {
+        let pane = self.focused_mut();
+        pane.buffer_idx = buffers.active_idx();
+        pane.view = buffers.active().view();
+    }
+
+    fn load_focused(&mut self, buffers: &mut Buffers) {
+        let pane = *self.focused_mut();
+        buffers.select(pane.buffer_idx);
+        buffers.active_mut().set_view(pane.view);
+    }
+
+    // Rendering
+
+    pub fn render<B: Backend>(&mut self, frame: &mut Frame<B>, rect: Rect, buffers: &mut Buffers) -> (u16, u16) {
+        let mut pane_rects = Vec::new();
+        let mut separators = Vec::new();
+        self.root.layout(rect, &mut pane_rects, &mut separators);
+
+        for (rect, borders) in separators {
+            frame.render_widget(Block::default().borders(borders), rect);
+        }
+
+        let mut cursor = (rect.x, rect.y);
+        for (idx, pane_rect) in pane_rects.iter().enumerate() {
+            // Resizing can still leave a pane in a small terminal without any room
+            if pane_rect.width == 0 || pane_rect.height == 0 {
+                continue;
+            }
+            let pane = self.root.leaf_mut(idx).expect("index should be within leaf count");
+            let file = buffers.get_mut(pane.buffer_idx);
+
+            // The focused pane's view lives in its file, so only other panes swap theirs in
+            let focused_view = file.view();
+            if idx != self.focused_idx {
+                file.set_view(pane.view);
+            }
+
+            file.adjust_viewport(*pane_rect);
+            file.render(frame, *pane_rect);
+
+            if idx == self.focused_idx {
+                cursor = file.global_cursor(*pane_rect);
+            } else {
+                pane.view = file.view();
+                file.set_view(focused_view);
+            }
+        }
+
+        self.pane_rects = pane_rects;
+        return cursor;
+    }
+}
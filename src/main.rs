@@ -1,22 +1,65 @@
-use std::io;
+#![allow(clippy::needless_return)]
 
-use crossterm::{terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}, execute};
+use std::{env, io};
+
+use crossterm::{terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}, event::{EnableBracketedPaste, DisableBracketedPaste}, execute};
 use tui::{backend::CrosstermBackend, Terminal};
 
+use app::file::File;
+
 mod app;
 
+fn parse_position(arg: &str) -> Option<(usize, usize)> {
+    let (line, col) = match arg.split_once(':') {
+        Some((line, col)) => (line, col.parse::<usize>().ok()?),
+        None => (arg, 1),
+    };
+    let line = line.parse::<usize>().ok()?;
+
+    // Positions are 1-based on the command line
+    return Some((col.saturating_sub(1), line.saturating_sub(1)));
+}
+
+fn parse_args() -> io::Result<File> {
+    let mut path = None;
+    let mut position = None;
+
+    for arg in env::args().skip(1) {
+        if let Some(position_arg) = arg.strip_prefix('+') {
+            match parse_position(position_arg) {
+                Some(pos) => position = Some(pos),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid position '{}', expected +LINE[:COL]", arg))),
+            }
+        } else {
+            path = Some(arg);
+        }
+    }
+
+    let mut file = match path {
+        Some(path) => File::open(&path)?,
+        None => File::new(),
+    };
+    if let Some((char_idx, line_idx)) = position {
+        file.set_cursor(char_idx, line_idx);
+    }
+
+    return Ok(file);
+}
+
 fn main() -> io::Result<()> {
+    let file = parse_args()?;
+
     let stdout = io::stdout();
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     enable_raw_mode()?;
-    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen, EnableBracketedPaste)?;
 
-    app::run(&mut terminal)?;
+    app::run(&mut terminal, file)?;
 
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(terminal.backend_mut(), DisableBracketedPaste, LeaveAlternateScreen)?;
 
     return Ok(());
 }